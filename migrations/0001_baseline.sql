@@ -0,0 +1,63 @@
+-- The schema the server ran on before migrations were introduced. Tables that
+-- already exist are left alone, so a database created by hand is adopted as
+-- is and brought up to date by the migrations that follow.
+CREATE TABLE IF NOT EXISTS property_owner (
+    owner_id UUID PRIMARY KEY,
+    owner_name VARCHAR(255) NOT NULL,
+    address TEXT NOT NULL,
+    email VARCHAR(255) NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS "user" (
+    user_id UUID PRIMARY KEY,
+    full_name VARCHAR(255) NOT NULL,
+    email_address VARCHAR(255) NOT NULL UNIQUE,
+    address TEXT NOT NULL,
+    password VARCHAR(255) NOT NULL
+);
+CREATE TABLE IF NOT EXISTS rent_property (
+    rent_property_id UUID PRIMARY KEY,
+    title VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL,
+    address TEXT NOT NULL,
+    owner_id UUID NOT NULL REFERENCES property_owner(owner_id),
+    lt INTEGER NOT NULL,
+    lb INTEGER NOT NULL,
+    bedroom SMALLINT NOT NULL,
+    bathroom SMALLINT NOT NULL,
+    monthly_rent BIGINT NOT NULL,
+    picture_url TEXT NOT NULL,
+    status VARCHAR(32) NOT NULL
+);
+CREATE TABLE IF NOT EXISTS rent_transaction (
+    rent_transaction_id UUID PRIMARY KEY,
+    rent_property_id UUID NOT NULL REFERENCES rent_property(rent_property_id),
+    user_id UUID NOT NULL REFERENCES "user"(user_id),
+    total_payment BIGINT,
+    start_date DATE NOT NULL,
+    end_date DATE,
+    status VARCHAR(32) NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sale_property (
+    sale_property_id UUID PRIMARY KEY,
+    title VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL,
+    address TEXT NOT NULL,
+    owner_id UUID NOT NULL REFERENCES property_owner(owner_id),
+    lt INTEGER NOT NULL,
+    lb INTEGER NOT NULL,
+    bedroom SMALLINT NOT NULL,
+    bathroom SMALLINT NOT NULL,
+    property_price BIGINT NOT NULL,
+    picture_url TEXT NOT NULL,
+    status VARCHAR(32) NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sale_transaction (
+    sale_transaction_id UUID PRIMARY KEY,
+    sale_property_id UUID NOT NULL REFERENCES sale_property(sale_property_id),
+    user_id UUID NOT NULL REFERENCES "user"(user_id),
+    down_payment BIGINT NOT NULL,
+    installment_duration INTEGER NOT NULL,
+    monthly_mortgage BIGINT NOT NULL,
+    sale_date DATE NOT NULL,
+    status VARCHAR(32) NOT NULL
+);
@@ -0,0 +1,10 @@
+CREATE TABLE user_session (
+    session_id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES "user"(user_id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_active TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX user_session_user_id_idx ON user_session(user_id);
+CREATE INDEX user_session_expires_at_idx ON user_session(expires_at);
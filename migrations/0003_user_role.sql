@@ -0,0 +1,3 @@
+CREATE TYPE user_role AS ENUM ('buyer', 'owner', 'admin');
+
+ALTER TABLE "user" ADD COLUMN role user_role NOT NULL DEFAULT 'buyer';
@@ -0,0 +1,2 @@
+ALTER TABLE property_owner
+    ADD COLUMN user_id UUID UNIQUE REFERENCES "user"(user_id) ON DELETE SET NULL;
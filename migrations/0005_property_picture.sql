@@ -0,0 +1,24 @@
+CREATE TABLE property_picture (
+    picture_id UUID PRIMARY KEY,
+    rent_property_id UUID REFERENCES rent_property(rent_property_id) ON DELETE CASCADE,
+    sale_property_id UUID REFERENCES sale_property(sale_property_id) ON DELETE CASCADE,
+    file_name TEXT NOT NULL,
+    picture_url TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    is_cover BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (num_nonnulls(rent_property_id, sale_property_id) = 1)
+);
+
+CREATE INDEX property_picture_rent_idx ON property_picture(rent_property_id, position);
+CREATE INDEX property_picture_sale_idx ON property_picture(sale_property_id, position);
+CREATE UNIQUE INDEX property_picture_rent_cover_idx ON property_picture(rent_property_id) WHERE is_cover;
+CREATE UNIQUE INDEX property_picture_sale_cover_idx ON property_picture(sale_property_id) WHERE is_cover;
+
+INSERT INTO property_picture(picture_id, rent_property_id, file_name, picture_url, position, is_cover)
+    SELECT gen_random_uuid(), rent_property_id, regexp_replace(picture_url, '^.*/', ''), picture_url, 0, TRUE
+    FROM rent_property;
+
+INSERT INTO property_picture(picture_id, sale_property_id, file_name, picture_url, position, is_cover)
+    SELECT gen_random_uuid(), sale_property_id, regexp_replace(picture_url, '^.*/', ''), picture_url, 0, TRUE
+    FROM sale_property;
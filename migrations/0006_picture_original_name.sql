@@ -0,0 +1,3 @@
+ALTER TABLE property_picture ADD COLUMN original_name TEXT;
+UPDATE property_picture SET original_name = file_name;
+ALTER TABLE property_picture ALTER COLUMN original_name SET NOT NULL;
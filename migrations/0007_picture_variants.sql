@@ -0,0 +1,10 @@
+ALTER TABLE property_picture ADD COLUMN variants JSONB;
+
+-- Pictures uploaded before processing existed only have the original file.
+UPDATE property_picture SET variants = jsonb_build_object(
+    'thumbnail', jsonb_build_object('url', picture_url, 'webp_url', NULL),
+    'medium', jsonb_build_object('url', picture_url, 'webp_url', NULL),
+    'full', jsonb_build_object('url', picture_url, 'webp_url', NULL)
+);
+
+ALTER TABLE property_picture ALTER COLUMN variants SET NOT NULL;
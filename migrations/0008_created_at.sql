@@ -0,0 +1,9 @@
+-- Listings and owners record when they were created so lists can be
+-- sorted newest first.
+ALTER TABLE property_owner ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+ALTER TABLE rent_property ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+ALTER TABLE sale_property ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX property_owner_created_at_idx ON property_owner(created_at, owner_id);
+CREATE INDEX rent_property_created_at_idx ON rent_property(created_at, rent_property_id);
+CREATE INDEX sale_property_created_at_idx ON sale_property(created_at, sale_property_id);
@@ -0,0 +1,44 @@
+-- A property can't be booked twice for overlapping dates. Ranges are
+-- [start_date, end_date): the end date is the move-out day and can be the
+-- next tenant's start date. Cancelled bookings don't hold dates.
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+-- Statuses set by hand may differ from these in case or spacing.
+UPDATE rent_transaction SET status = initcap(btrim(status))
+    WHERE status != initcap(btrim(status)) AND initcap(btrim(status)) IN ('Unpaid', 'Installment', 'Paid', 'Cancelled');
+UPDATE sale_transaction SET status = initcap(btrim(status))
+    WHERE status != initcap(btrim(status)) AND initcap(btrim(status)) IN ('Unpaid', 'Installment', 'Paid', 'Cancelled');
+
+-- Bookings made before dates were checked. A stay ending on or before the day
+-- it starts holds no dates, so it is cancelled.
+UPDATE rent_transaction SET status = 'Cancelled'
+    WHERE status != 'Cancelled' AND end_date <= start_date;
+
+-- Double bookings are resolved in favour of paid bookings, then the one
+-- starting first; the others are cancelled. No payment went through a
+-- provider before 0012, so there is nothing to refund.
+DO $$
+DECLARE
+    booking RECORD;
+    kept UUID[] := '{}';
+BEGIN
+    FOR booking IN
+        SELECT rent_transaction_id, rent_property_id, start_date, end_date FROM rent_transaction
+        WHERE status != 'Cancelled'
+        ORDER BY status = 'Paid' DESC, start_date, rent_transaction_id
+    LOOP
+        IF EXISTS (
+            SELECT 1 FROM rent_transaction
+            WHERE rent_transaction_id = ANY(kept) AND rent_property_id = booking.rent_property_id
+                AND daterange(start_date, end_date, '[)') && daterange(booking.start_date, booking.end_date, '[)')
+        ) THEN
+            UPDATE rent_transaction SET status = 'Cancelled' WHERE rent_transaction_id = booking.rent_transaction_id;
+        ELSE
+            kept := kept || booking.rent_transaction_id;
+        END IF;
+    END LOOP;
+END $$;
+
+ALTER TABLE rent_transaction ADD CONSTRAINT rent_transaction_no_overlap
+    EXCLUDE USING gist (rent_property_id WITH =, daterange(start_date, end_date, '[)') WITH &&)
+    WHERE (status != 'Cancelled');
@@ -0,0 +1,2 @@
+-- Shortest period, in days, a property can be rented for.
+ALTER TABLE rent_property ADD COLUMN min_rent_days INT NOT NULL DEFAULT 1 CHECK (min_rent_days >= 1);
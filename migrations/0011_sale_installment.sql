@@ -0,0 +1,17 @@
+-- Installment ledger of a sale, generated from its mortgage plan.
+ALTER TABLE sale_transaction ADD COLUMN down_payment_paid_at TIMESTAMPTZ;
+
+CREATE TABLE sale_installment (
+    sale_transaction_id UUID NOT NULL REFERENCES sale_transaction(sale_transaction_id) ON DELETE CASCADE,
+    installment_number INTEGER NOT NULL CHECK (installment_number >= 1),
+    due_date DATE NOT NULL,
+    amount BIGINT NOT NULL,
+    principal BIGINT NOT NULL,
+    interest BIGINT NOT NULL,
+    balance BIGINT NOT NULL,
+    status VARCHAR(32) NOT NULL DEFAULT 'Unpaid',
+    paid_at TIMESTAMPTZ,
+    PRIMARY KEY (sale_transaction_id, installment_number)
+);
+
+CREATE INDEX sale_installment_unpaid_due ON sale_installment (due_date) WHERE status = 'Unpaid';
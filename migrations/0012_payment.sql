@@ -0,0 +1,21 @@
+-- Payment attempts made through the payment provider. installment_number is
+-- NULL for rent payments and sale down payments.
+CREATE TABLE payment (
+    payment_id UUID PRIMARY KEY,
+    transaction_kind VARCHAR(16) NOT NULL CHECK (transaction_kind IN ('Rent', 'Sale')),
+    transaction_id UUID NOT NULL,
+    installment_number INTEGER,
+    user_id UUID NOT NULL REFERENCES "user"(user_id),
+    amount BIGINT NOT NULL CHECK (amount >= 0),
+    provider VARCHAR(32) NOT NULL,
+    provider_reference VARCHAR(255) UNIQUE,
+    checkout_url TEXT,
+    status VARCHAR(32) NOT NULL DEFAULT 'Pending',
+    failure_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    settled_at TIMESTAMPTZ
+);
+
+-- At most one attempt in flight for anything payable.
+CREATE UNIQUE INDEX payment_one_pending ON payment (transaction_kind, transaction_id, COALESCE(installment_number, 0))
+    WHERE status = 'Pending';
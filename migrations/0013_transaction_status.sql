@@ -0,0 +1,32 @@
+-- Rent transactions are Unpaid, Paid or Cancelled. Sales also go through
+-- Installment between the down payment and the last installment.
+CREATE TYPE transaction_status AS ENUM ('Unpaid', 'Installment', 'Paid', 'Cancelled');
+
+ALTER TABLE rent_transaction DROP CONSTRAINT rent_transaction_no_overlap;
+
+-- 0009 normalized the case of statuses. Anything else can't be mapped safely
+-- and stops the migration.
+DO $$
+DECLARE
+    unknown TEXT;
+BEGIN
+    SELECT string_agg(DISTINCT status, ', ') INTO unknown FROM (
+        SELECT status FROM rent_transaction UNION ALL SELECT status FROM sale_transaction
+    ) statuses WHERE status NOT IN ('Unpaid', 'Installment', 'Paid', 'Cancelled');
+
+    IF unknown IS NOT NULL THEN
+        RAISE EXCEPTION 'Transactions have unknown statuses: %. Set them to Unpaid, Paid or Cancelled and rerun.', unknown;
+    END IF;
+END $$;
+
+ALTER TABLE rent_transaction ALTER COLUMN status TYPE transaction_status USING status::transaction_status;
+ALTER TABLE sale_transaction ALTER COLUMN status TYPE transaction_status USING status::transaction_status;
+
+ALTER TABLE rent_transaction ADD CONSTRAINT rent_transaction_no_overlap
+    EXCLUDE USING gist (rent_property_id WITH =, daterange(start_date, end_date, '[)') WITH &&)
+    WHERE (status != 'Cancelled');
+
+-- Set when a transaction is cancelled; refund_amount is what the buyer or
+-- tenant gets back under the cancellation policy.
+ALTER TABLE rent_transaction ADD COLUMN cancelled_at TIMESTAMPTZ, ADD COLUMN refund_amount BIGINT;
+ALTER TABLE sale_transaction ADD COLUMN cancelled_at TIMESTAMPTZ, ADD COLUMN refund_amount BIGINT;
@@ -0,0 +1,9 @@
+-- Unpaid transactions are cancelled once their payment deadline passes.
+ALTER TABLE rent_transaction ADD COLUMN payment_deadline TIMESTAMPTZ;
+ALTER TABLE sale_transaction ADD COLUMN payment_deadline TIMESTAMPTZ;
+
+UPDATE rent_transaction SET payment_deadline = NOW() + INTERVAL '24 hours' WHERE status = 'Unpaid';
+UPDATE sale_transaction SET payment_deadline = NOW() + INTERVAL '24 hours' WHERE status = 'Unpaid';
+
+CREATE INDEX rent_transaction_unpaid_deadline ON rent_transaction (payment_deadline) WHERE status = 'Unpaid';
+CREATE INDEX sale_transaction_unpaid_deadline ON sale_transaction (payment_deadline) WHERE status = 'Unpaid';
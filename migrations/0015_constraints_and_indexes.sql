@@ -0,0 +1,71 @@
+-- Constraints the application relies on but the schema didn't enforce, and
+-- indexes for the lookups it makes by foreign key.
+--
+-- Listing statuses are fixed up where they differ only in case or spacing.
+-- Listings with any other status are hidden rather than dropped.
+UPDATE rent_property SET status = CASE
+        WHEN initcap(btrim(status)) IN ('Available', 'Unlisted', 'Deleted') THEN initcap(btrim(status))
+        ELSE 'Unlisted'
+    END
+    WHERE status NOT IN ('Available', 'Unlisted', 'Deleted');
+UPDATE sale_property SET status = CASE
+        WHEN initcap(btrim(status)) IN ('Available', 'Unlisted', 'Deleted') THEN initcap(btrim(status))
+        ELSE 'Unlisted'
+    END
+    WHERE status NOT IN ('Available', 'Unlisted', 'Deleted');
+
+-- A property is sold at most once; cancelled sales release it, as enforced by
+-- sale_transaction_one_active below. Where a property was sold more than
+-- once, the sale furthest along is kept, then the earliest. The others are
+-- cancelled with everything paid towards them refunded, since the buyer did
+-- nothing to forfeit it.
+WITH ranked AS (
+    SELECT sale_transaction_id, ROW_NUMBER() OVER (
+        PARTITION BY sale_property_id
+        ORDER BY array_position(ARRAY['Paid', 'Installment', 'Unpaid']::transaction_status[], status), sale_date, sale_transaction_id
+    ) AS rank
+    FROM sale_transaction WHERE status != 'Cancelled'
+), duplicates AS (
+    SELECT sale_transaction_id FROM ranked WHERE rank > 1
+), installments AS (
+    UPDATE sale_installment SET status = 'Cancelled'
+    WHERE sale_transaction_id IN (SELECT sale_transaction_id FROM duplicates) AND status = 'Unpaid'
+)
+UPDATE sale_transaction st SET status = 'Cancelled', cancelled_at = NOW(), refund_amount =
+    CASE WHEN st.down_payment_paid_at IS NULL THEN 0 ELSE st.down_payment END
+    + (SELECT COALESCE(SUM(amount), 0) FROM sale_installment si WHERE si.sale_transaction_id = st.sale_transaction_id AND si.status = 'Paid')
+WHERE st.sale_transaction_id IN (SELECT sale_transaction_id FROM duplicates);
+
+-- Amounts entered before input was validated can't be corrected here, so
+-- these checks are NOT VALID: they hold for new and updated rows only, and
+-- such rows must be corrected by hand before they can be changed.
+ALTER TABLE rent_property
+    ADD CONSTRAINT rent_property_status_check CHECK (status IN ('Available', 'Unlisted', 'Deleted')),
+    ADD CONSTRAINT rent_property_monthly_rent_check CHECK (monthly_rent >= 0) NOT VALID;
+
+ALTER TABLE sale_property
+    ADD CONSTRAINT sale_property_status_check CHECK (status IN ('Available', 'Unlisted', 'Deleted')),
+    ADD CONSTRAINT sale_property_property_price_check CHECK (property_price >= 0) NOT VALID;
+
+-- Bookings with inverted dates were cancelled by 0009.
+ALTER TABLE rent_transaction
+    ADD CONSTRAINT rent_transaction_dates_check CHECK (status = 'Cancelled' OR end_date IS NULL OR end_date > start_date);
+
+ALTER TABLE sale_transaction
+    ADD CONSTRAINT sale_transaction_down_payment_check CHECK (down_payment >= 0) NOT VALID,
+    ADD CONSTRAINT sale_transaction_installment_duration_check CHECK (installment_duration >= 1) NOT VALID;
+
+ALTER TABLE sale_installment
+    ADD CONSTRAINT sale_installment_status_check CHECK (status IN ('Unpaid', 'Paid', 'Cancelled'));
+
+ALTER TABLE payment
+    ADD CONSTRAINT payment_status_check CHECK (status IN ('Pending', 'Succeeded', 'Failed'));
+
+CREATE UNIQUE INDEX sale_transaction_one_active ON sale_transaction (sale_property_id) WHERE status != 'Cancelled';
+
+CREATE INDEX rent_property_owner_idx ON rent_property (owner_id);
+CREATE INDEX sale_property_owner_idx ON sale_property (owner_id);
+CREATE INDEX rent_transaction_user_idx ON rent_transaction (user_id, start_date);
+CREATE INDEX sale_transaction_user_idx ON sale_transaction (user_id, sale_date);
+CREATE INDEX payment_transaction_idx ON payment (transaction_kind, transaction_id);
+CREATE INDEX payment_user_idx ON payment (user_id);
@@ -0,0 +1,4 @@
+-- Set when a payment succeeds for something that no longer accepts it, such
+-- as a transaction cancelled while the payment was in flight. The amount is
+-- owed back to the user.
+ALTER TABLE payment ADD COLUMN refund_amount BIGINT CHECK (refund_amount >= 0);
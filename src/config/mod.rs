@@ -0,0 +1,321 @@
+use std::{env, fmt::Display, fs, ops::RangeInclusive, path::Path, str::FromStr};
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::utils::logging::Secret;
+
+/// File read when `CONFIG_FILE` isn't set, if it exists.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Server settings, loaded once at startup.
+///
+/// Each field can be set in a TOML file under its own name, and overridden
+/// by the environment variable of the same name in upper case, e.g.
+/// `database_max_connections` and `DATABASE_MAX_CONNECTIONS`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database_url: String,
+    pub database_max_connections: u32,
+    /// Apply pending migrations on startup.
+    pub run_migrations: bool,
+    pub address: String,
+    pub port: u16,
+    /// Public base URL of this server, used to build links to uploaded files.
+    pub host_url: String,
+    /// Origins allowed to call the API from a browser. A comma-separated list
+    /// in the environment.
+    pub cors_origins: Vec<String>,
+    /// `local` or `s3`.
+    pub storage_backend: String,
+    /// Where the local storage backend keeps uploaded files.
+    pub upload_dir: String,
+    /// Required with the `s3` backend, as are both keys.
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<Secret>,
+    /// An S3-compatible server such as MinIO, instead of AWS.
+    pub s3_endpoint: Option<String>,
+    /// Base URL files are served from when the bucket is public. Otherwise
+    /// they are proxied through this server.
+    pub s3_public_url: Option<String>,
+    /// Largest multipart request accepted, in bytes.
+    pub max_upload_bytes: usize,
+    /// `postgres` or `memory`.
+    pub session_store: String,
+    pub session_idle_timeout_hours: i64,
+    pub session_max_lifetime_hours: i64,
+    /// How often expired sessions are evicted.
+    pub session_sweep_interval_secs: u64,
+    /// Which events are logged, as a filter such as `info,sqlx=warn`.
+    pub log_level: String,
+    /// `text` or `json`, one object per line.
+    pub log_format: String,
+    /// How long in-flight requests may run after a shutdown signal before
+    /// they are cut off.
+    pub shutdown_timeout_secs: u64,
+    /// Payment gateway. Required; `mock` is the only one so far, and moves no
+    /// money.
+    pub payment_provider: String,
+    /// Key webhooks are signed with.
+    pub payment_webhook_secret: Option<Secret>,
+    /// Lets admins complete mock payments through
+    /// `POST /api/payments/mock/{reference}/{outcome}`. For development only.
+    pub payment_mock_endpoint: bool,
+    /// How long a new transaction holds its property while waiting for
+    /// payment.
+    pub payment_window_hours: i64,
+    /// How often transactions past their payment window are cancelled.
+    pub reservation_sweep_interval_secs: u64,
+    /// Paid rent cancelled at least this many days before it starts is
+    /// refunded in full.
+    pub rent_full_refund_days: i64,
+    /// Share of paid rent refunded when cancelled closer to its start.
+    pub rent_late_refund_ratio: f64,
+    /// Share of what was paid towards a sale that is kept on cancellation.
+    pub sale_cancellation_fee_ratio: f64,
+    /// Annual rate charged during the fixed period of a mortgage.
+    pub mortgage_fixed_rate: f64,
+    /// Years the fixed rate lasts. Unset keeps it for the whole loan.
+    pub mortgage_fixed_years: Option<i32>,
+    /// Annual rate after the fixed period. Unset means the fixed rate.
+    pub mortgage_floating_rate: Option<f64>,
+    /// Smallest share of the price that must be paid up front.
+    pub mortgage_min_down_payment_ratio: f64,
+    pub mortgage_min_months: i32,
+    pub mortgage_max_months: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            database_url: String::new(),
+            database_max_connections: 5,
+            run_migrations: true,
+            address: "127.0.0.1".to_string(),
+            port: 8080,
+            host_url: String::new(),
+            cors_origins: vec!["http://127.0.0.1:5500".to_string(), "https://renoob21.github.io".to_string()],
+            storage_backend: "local".to_string(),
+            upload_dir: "./uploaded".to_string(),
+            s3_bucket: None,
+            s3_region: "us-east-1".to_string(),
+            s3_access_key_id: None,
+            s3_secret_access_key: None,
+            s3_endpoint: None,
+            s3_public_url: None,
+            max_upload_bytes: 50 * 1024 * 1024,
+            session_store: "postgres".to_string(),
+            session_idle_timeout_hours: 48,
+            session_max_lifetime_hours: 720,
+            session_sweep_interval_secs: 600,
+            log_level: "info".to_string(),
+            log_format: "text".to_string(),
+            shutdown_timeout_secs: 30,
+            payment_provider: String::new(),
+            payment_webhook_secret: None,
+            payment_mock_endpoint: false,
+            payment_window_hours: 24,
+            reservation_sweep_interval_secs: 60,
+            rent_full_refund_days: 7,
+            rent_late_refund_ratio: 0.5,
+            sale_cancellation_fee_ratio: 0.1,
+            mortgage_fixed_rate: 0.06,
+            mortgage_fixed_years: None,
+            mortgage_floating_rate: None,
+            mortgage_min_down_payment_ratio: 0.1,
+            mortgage_min_months: 12,
+            mortgage_max_months: 360,
+        }
+    }
+}
+
+fn override_from_env<T: FromStr>(target: &mut T, name: &str, problems: &mut Vec<String>) where T::Err: Display {
+    if let Ok(raw) = env::var(name) {
+        match raw.parse() {
+            Ok(value) => *target = value,
+            Err(err) => problems.push(format!("{}: {}", name, err)),
+        }
+    }
+}
+
+fn override_optional_from_env<T: FromStr>(target: &mut Option<T>, name: &str, problems: &mut Vec<String>) where T::Err: Display {
+    if let Ok(raw) = env::var(name) {
+        match raw.parse() {
+            Ok(value) => *target = Some(value),
+            Err(err) => problems.push(format!("{}: {}", name, err)),
+        }
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+impl Config {
+    /// Reads the file named by `CONFIG_FILE`, or `config.toml` if present,
+    /// then applies environment overrides and validates the result. Every
+    /// problem found is reported, one per line.
+    pub fn load() -> Result<Config, String> {
+        let mut config = match env::var("CONFIG_FILE") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).is_file() => Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            Err(_) => Config::default(),
+        };
+
+        let mut problems = Vec::new();
+
+        override_from_env(&mut config.database_url, "DATABASE_URL", &mut problems);
+        override_from_env(&mut config.database_max_connections, "DATABASE_MAX_CONNECTIONS", &mut problems);
+        override_from_env(&mut config.run_migrations, "RUN_MIGRATIONS", &mut problems);
+        override_from_env(&mut config.address, "ADDRESS", &mut problems);
+        override_from_env(&mut config.port, "PORT", &mut problems);
+        override_from_env(&mut config.host_url, "HOST_URL", &mut problems);
+        override_from_env(&mut config.storage_backend, "STORAGE_BACKEND", &mut problems);
+        override_from_env(&mut config.upload_dir, "UPLOAD_DIR", &mut problems);
+        override_optional_from_env(&mut config.s3_bucket, "S3_BUCKET", &mut problems);
+        override_from_env(&mut config.s3_region, "S3_REGION", &mut problems);
+        override_optional_from_env(&mut config.s3_access_key_id, "S3_ACCESS_KEY_ID", &mut problems);
+        override_optional_from_env(&mut config.s3_secret_access_key, "S3_SECRET_ACCESS_KEY", &mut problems);
+        override_optional_from_env(&mut config.s3_endpoint, "S3_ENDPOINT", &mut problems);
+        override_optional_from_env(&mut config.s3_public_url, "S3_PUBLIC_URL", &mut problems);
+        override_from_env(&mut config.max_upload_bytes, "MAX_UPLOAD_BYTES", &mut problems);
+        override_from_env(&mut config.session_store, "SESSION_STORE", &mut problems);
+        override_from_env(&mut config.session_idle_timeout_hours, "SESSION_IDLE_TIMEOUT_HOURS", &mut problems);
+        override_from_env(&mut config.session_max_lifetime_hours, "SESSION_MAX_LIFETIME_HOURS", &mut problems);
+        override_from_env(&mut config.session_sweep_interval_secs, "SESSION_SWEEP_INTERVAL_SECS", &mut problems);
+        override_from_env(&mut config.log_level, "LOG_LEVEL", &mut problems);
+        override_from_env(&mut config.log_format, "LOG_FORMAT", &mut problems);
+        override_from_env(&mut config.shutdown_timeout_secs, "SHUTDOWN_TIMEOUT_SECS", &mut problems);
+        override_from_env(&mut config.payment_provider, "PAYMENT_PROVIDER", &mut problems);
+        override_optional_from_env(&mut config.payment_webhook_secret, "PAYMENT_WEBHOOK_SECRET", &mut problems);
+        override_from_env(&mut config.payment_mock_endpoint, "PAYMENT_MOCK_ENDPOINT", &mut problems);
+        override_from_env(&mut config.payment_window_hours, "PAYMENT_WINDOW_HOURS", &mut problems);
+        override_from_env(&mut config.reservation_sweep_interval_secs, "RESERVATION_SWEEP_INTERVAL_SECS", &mut problems);
+        override_from_env(&mut config.rent_full_refund_days, "RENT_FULL_REFUND_DAYS", &mut problems);
+        override_from_env(&mut config.rent_late_refund_ratio, "RENT_LATE_REFUND_RATIO", &mut problems);
+        override_from_env(&mut config.sale_cancellation_fee_ratio, "SALE_CANCELLATION_FEE_RATIO", &mut problems);
+        override_from_env(&mut config.mortgage_fixed_rate, "MORTGAGE_FIXED_RATE", &mut problems);
+        override_optional_from_env(&mut config.mortgage_fixed_years, "MORTGAGE_FIXED_YEARS", &mut problems);
+        override_optional_from_env(&mut config.mortgage_floating_rate, "MORTGAGE_FLOATING_RATE", &mut problems);
+        override_from_env(&mut config.mortgage_min_down_payment_ratio, "MORTGAGE_MIN_DOWN_PAYMENT_RATIO", &mut problems);
+        override_from_env(&mut config.mortgage_min_months, "MORTGAGE_MIN_MONTHS", &mut problems);
+        override_from_env(&mut config.mortgage_max_months, "MORTGAGE_MAX_MONTHS", &mut problems);
+
+        if let Ok(origins) = env::var("CORS_ORIGINS") {
+            config.cors_origins = origins.split(',').map(str::trim).filter(|origin| !origin.is_empty()).map(String::from).collect();
+        }
+
+        problems.extend(config.problems());
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+
+        toml::from_str(&contents).map_err(|err| format!("Invalid {}: {}", path.display(), err))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.database_url.is_empty() {
+            problems.push("database_url is required".to_string());
+        }
+        if self.database_max_connections == 0 {
+            problems.push("database_max_connections must be at least 1".to_string());
+        }
+        if self.port == 0 {
+            problems.push("port must not be 0".to_string());
+        }
+        if !is_http_url(&self.host_url) {
+            problems.push("host_url is required and must start with http:// or https://".to_string());
+        }
+        if let Some(origin) = self.cors_origins.iter().find(|origin| !is_http_url(origin)) {
+            problems.push(format!("cors_origins: {} must start with http:// or https://", origin));
+        }
+        match self.storage_backend.as_str() {
+            "local" => {},
+            "s3" => {
+                if self.s3_bucket.is_none() || self.s3_access_key_id.is_none() || self.s3_secret_access_key.is_none() {
+                    problems.push("storage_backend = s3 needs s3_bucket, s3_access_key_id and s3_secret_access_key".to_string());
+                }
+                if let Some(url) = [&self.s3_endpoint, &self.s3_public_url].into_iter().flatten().find(|url| !is_http_url(url)) {
+                    problems.push(format!("s3_endpoint and s3_public_url must start with http:// or https://, not {}", url));
+                }
+            },
+            other => problems.push(format!("storage_backend must be local or s3, not {}", other)),
+        }
+        if self.upload_dir.is_empty() {
+            problems.push("upload_dir must not be empty".to_string());
+        }
+        if self.max_upload_bytes == 0 {
+            problems.push("max_upload_bytes must be at least 1".to_string());
+        }
+        if !["postgres", "memory"].contains(&self.session_store.as_str()) {
+            problems.push(format!("session_store must be postgres or memory, not {}", self.session_store));
+        }
+        if self.session_idle_timeout_hours < 1 || self.session_max_lifetime_hours < 1 {
+            problems.push("session_idle_timeout_hours and session_max_lifetime_hours must be at least 1".to_string());
+        } else if self.session_idle_timeout_hours > self.session_max_lifetime_hours {
+            problems.push("session_idle_timeout_hours must not exceed session_max_lifetime_hours".to_string());
+        }
+        if self.session_sweep_interval_secs == 0 || self.reservation_sweep_interval_secs == 0 {
+            problems.push("session_sweep_interval_secs and reservation_sweep_interval_secs must be at least 1".to_string());
+        }
+        if let Err(err) = EnvFilter::try_new(&self.log_level) {
+            problems.push(format!("log_level: {}", err));
+        }
+        if !["text", "json"].contains(&self.log_format.as_str()) {
+            problems.push(format!("log_format must be text or json, not {}", self.log_format));
+        }
+        match self.payment_provider.as_str() {
+            "" => problems.push("payment_provider is required; mock is the only provider so far".to_string()),
+            "mock" => {},
+            other => problems.push(format!("payment_provider must be mock, not {}", other)),
+        }
+        if self.payment_mock_endpoint && self.payment_provider != "mock" {
+            problems.push("payment_mock_endpoint needs payment_provider = mock".to_string());
+        }
+        if self.payment_window_hours < 1 {
+            problems.push("payment_window_hours must be at least 1".to_string());
+        }
+        if self.rent_full_refund_days < 0 {
+            problems.push("rent_full_refund_days must not be negative".to_string());
+        }
+
+        const RATIO: RangeInclusive<f64> = 0.0..=1.0;
+        for (name, ratio) in [
+            ("rent_late_refund_ratio", self.rent_late_refund_ratio),
+            ("sale_cancellation_fee_ratio", self.sale_cancellation_fee_ratio),
+            ("mortgage_min_down_payment_ratio", self.mortgage_min_down_payment_ratio),
+        ] {
+            if !RATIO.contains(&ratio) {
+                problems.push(format!("{} must be between 0 and 1, not {}", name, ratio));
+            }
+        }
+
+        for (name, rate) in [("mortgage_fixed_rate", Some(self.mortgage_fixed_rate)), ("mortgage_floating_rate", self.mortgage_floating_rate)] {
+            if let Some(rate) = rate.filter(|rate| !rate.is_finite() || *rate < 0.0) {
+                problems.push(format!("{} must be 0 or more, not {}", name, rate));
+            }
+        }
+        if self.mortgage_fixed_years.is_some_and(|years| years < 1) {
+            problems.push("mortgage_fixed_years must be at least 1 when set".to_string());
+        }
+        if self.mortgage_min_months < 1 {
+            problems.push("mortgage_min_months must be at least 1".to_string());
+        } else if self.mortgage_min_months > self.mortgage_max_months {
+            problems.push("mortgage_min_months must not exceed mortgage_max_months".to_string());
+        }
+
+        problems
+    }
+}
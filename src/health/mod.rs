@@ -0,0 +1,130 @@
+use std::{future::Future, time::{Duration, Instant}};
+
+use actix_web::{get, rt, web::{self, ServiceConfig}, HttpResponse};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{storage::Storage, utils::models::ApiResponse, AppState, MIGRATOR};
+
+/// How long a single readiness check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// SQLSTATE of a query on a table that doesn't exist.
+const UNDEFINED_TABLE: &str = "42P01";
+
+#[derive(Debug, Serialize)]
+struct Health {
+    status: &'static str,
+    version: &'static str,
+}
+
+/// Outcome of one readiness check.
+#[derive(Debug, Serialize)]
+struct Check {
+    status: &'static str,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    status: &'static str,
+    database: Check,
+    storage: Check,
+    migrations: Check,
+}
+
+/// Runs `check` under `CHECK_TIMEOUT`. It passes with `Ok`, whose value is an
+/// optional detail to report alongside.
+async fn run_check(check: impl Future<Output = Result<Option<String>, String>>) -> Check {
+    let started = Instant::now();
+
+    let (status, detail) = match rt::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(detail)) => ("ok", detail),
+        Ok(Err(err)) => ("failing", Some(err)),
+        Err(_) => ("failing", Some(format!("Timed out after {}s", CHECK_TIMEOUT.as_secs()))),
+    };
+
+    Check { status, latency_ms: started.elapsed().as_secs_f64() * 1000.0, detail }
+}
+
+async fn check_database(db_pool: &PgPool) -> Result<Option<String>, String> {
+    sqlx::query_scalar!("SELECT 1 AS \"one!\"").fetch_one(db_pool).await.map_err(|err| err.to_string())?;
+
+    Ok(None)
+}
+
+/// Checks the configured storage is reachable. The check is read-only, so
+/// frequent probes neither cost writes nor leave files behind.
+async fn check_storage(storage: &dyn Storage) -> Result<Option<String>, String> {
+    storage.probe().await?;
+
+    Ok(None)
+}
+
+/// Fails while any migration this build knows of is unapplied. Migrations
+/// from newer builds are reported but tolerated, so old instances stay ready
+/// during a rolling deploy.
+async fn check_migrations(db_pool: &PgPool) -> Result<Option<String>, String> {
+    // Checked at runtime: the table belongs to sqlx and may not exist yet.
+    let applied: Vec<i64> = match sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version").fetch_all(db_pool).await {
+        // No migration has ever run against this database.
+        Err(sqlx::Error::Database(db_err)) if db_err.code().as_deref() == Some(UNDEFINED_TABLE) => Vec::new(),
+        result => result.map_err(|err| err.to_string())?,
+    };
+
+    let known: Vec<i64> = MIGRATOR.iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .collect();
+
+    let pending: Vec<String> = known.iter().filter(|version| !applied.contains(version)).map(i64::to_string).collect();
+    if !pending.is_empty() {
+        return Err(format!("Pending migrations: {}", pending.join(", ")));
+    }
+
+    let unknown = applied.iter().filter(|version| !known.contains(version)).count();
+
+    Ok((unknown > 0).then(|| format!("{} migration(s) applied by a newer build", unknown)))
+}
+
+/// Liveness: the process is up and serving requests.
+#[get("/healthz")]
+async fn get_healthz() -> HttpResponse {
+    HttpResponse::Ok().json(
+        ApiResponse::new(true, "OK".to_string(), Some(Health { status: "ok", version: env!("CARGO_PKG_VERSION") }), None)
+    )
+}
+
+/// Readiness: the database and the storage backend answer and the schema is
+/// up to date. Responds 503 while any check fails.
+#[get("/readyz")]
+async fn get_readyz(app_state: web::Data<AppState>) -> HttpResponse {
+    let database = run_check(check_database(&app_state.db_pool)).await;
+    let storage = run_check(check_storage(app_state.storage.as_ref())).await;
+    let migrations = run_check(check_migrations(&app_state.db_pool)).await;
+
+    let ready = database.is_ok() && storage.is_ok() && migrations.is_ok();
+    let readiness = Readiness { status: if ready { "ready" } else { "not_ready" }, database, storage, migrations };
+
+    if ready {
+        HttpResponse::Ok().json(ApiResponse::new(true, "Ready".to_string(), Some(readiness), None))
+    } else {
+        HttpResponse::ServiceUnavailable().json(
+            ApiResponse::new(false, "Not ready".to_string(), Some(readiness), Some("Error: One or more checks failed".to_string()))
+        )
+    }
+}
+
+pub fn init_routes(cfg: &mut ServiceConfig) {
+    cfg
+        .service(get_healthz)
+        .service(get_readyz);
+}
@@ -0,0 +1,116 @@
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{owner::get_owner_by_user, user::Role, utils::{error::AppError, models::Session}};
+
+/// Query-string filters accepted by the rent and sale listing endpoints.
+/// Price bounds apply to `monthly_rent` or `property_price`.
+#[derive(Debug, Deserialize)]
+pub struct ListingFilter {
+    pub min_price: Option<i64>,
+    pub max_price: Option<i64>,
+    pub min_bedroom: Option<i16>,
+    pub min_bathroom: Option<i16>,
+    pub min_lt: Option<i32>,
+    pub max_lt: Option<i32>,
+    pub min_lb: Option<i32>,
+    pub max_lb: Option<i32>,
+    pub owner: Option<Uuid>,
+    pub status: Option<String>,
+    /// Free text matched against title, description and address.
+    pub q: Option<String>,
+}
+
+fn check_range<T: PartialOrd>(min: Option<T>, max: Option<T>, name: &str) -> Result<(), String> {
+    match (min, max) {
+        (Some(min), Some(max)) if min > max => Err(format!("Error: min_{} is greater than max_{}", name, name)),
+        _ => Ok(()),
+    }
+}
+
+/// Escapes `LIKE` wildcards so user input only matches literally.
+fn like_pattern(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
+
+impl ListingFilter {
+    /// Validates the filter and settles which listings the caller may see.
+    /// Anyone can browse `Available` listings, the default. `Unlisted` ones
+    /// are limited to admins and to owners looking at their own.
+    pub async fn authorize(&mut self, db_pool: &PgPool, session: Option<&Session>) -> Result<(), AppError> {
+        check_range(self.min_price, self.max_price, "price")
+            .and(check_range(self.min_lt, self.max_lt, "lt"))
+            .and(check_range(self.min_lb, self.max_lb, "lb"))
+            .map_err(AppError::BadRequest)?;
+
+        match self.status.get_or_insert_with(|| "Available".to_string()).as_str() {
+            "Available" => return Ok(()),
+            "Unlisted" => (),
+            _ => return Err(AppError::BadRequest("Error: status must be Available or Unlisted".to_string())),
+        }
+
+        let session = match session {
+            Some(session) => session,
+            None => return Err(AppError::Forbidden("Login required to browse unlisted properties".to_string()))
+        };
+
+        if session.user_data.role == Role::Admin {
+            return Ok(());
+        }
+
+        match get_owner_by_user(db_pool, session.user_data.user_id).await? {
+            Some(owner) if self.owner.is_none() || self.owner == Some(owner.owner_id) => {
+                self.owner = Some(owner.owner_id);
+                Ok(())
+            },
+            _ => Err(AppError::Forbidden("Only the owner can browse their unlisted properties".to_string())),
+        }
+    }
+
+    /// Appends an `AND` condition for every filter that is set. Values are
+    /// always bound as parameters; `price_column` must be a trusted column name.
+    pub fn push_conditions(&self, query: &mut QueryBuilder<'_, Postgres>, price_column: &str) {
+        if let Some(min_price) = self.min_price {
+            query.push(format!(" AND {} >= ", price_column)).push_bind(min_price);
+        }
+        if let Some(max_price) = self.max_price {
+            query.push(format!(" AND {} <= ", price_column)).push_bind(max_price);
+        }
+        if let Some(min_bedroom) = self.min_bedroom {
+            query.push(" AND bedroom >= ").push_bind(min_bedroom);
+        }
+        if let Some(min_bathroom) = self.min_bathroom {
+            query.push(" AND bathroom >= ").push_bind(min_bathroom);
+        }
+        if let Some(min_lt) = self.min_lt {
+            query.push(" AND lt >= ").push_bind(min_lt);
+        }
+        if let Some(max_lt) = self.max_lt {
+            query.push(" AND lt <= ").push_bind(max_lt);
+        }
+        if let Some(min_lb) = self.min_lb {
+            query.push(" AND lb >= ").push_bind(min_lb);
+        }
+        if let Some(max_lb) = self.max_lb {
+            query.push(" AND lb <= ").push_bind(max_lb);
+        }
+        if let Some(owner) = self.owner {
+            query.push(" AND owner_id = ").push_bind(owner);
+        }
+
+        query.push(" AND status = ").push_bind(self.status.clone().unwrap_or("Available".to_string()));
+
+        if let Some(text) = self.q.as_deref().map(str::trim).filter(|text| !text.is_empty()) {
+            let pattern = like_pattern(text);
+
+            query
+                .push(" AND (title ILIKE ").push_bind(pattern.clone())
+                .push(" OR description ILIKE ").push_bind(pattern.clone())
+                .push(" OR address ILIKE ").push_bind(pattern)
+                .push(")");
+        }
+    }
+}
@@ -1,24 +1,79 @@
-use std::{collections::HashMap, env, sync::{Arc, Mutex}};
+use std::{env, io, path::PathBuf, process, sync::Arc, time::Duration};
 
 use actix_cors::Cors;
+use actix_multipart::form::MultipartFormConfig;
 use actix_files::Files;
-use chrono::Local;
+use config::Config;
 use dotenv::dotenv;
 
-use actix_web::{get, http::{self, header::HeaderName}, web, App, HttpResponse, HttpServer, Responder};
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use utils::models::Session;
-
+use actix_web::{get, http::{self, header::HeaderName}, middleware::from_fn, web, App, HttpResponse, HttpServer, Responder};
+use metrics::Metrics;
+use payment::PaymentProvider;
+use sale_property::mortgage::MortgagePolicy;
+use sqlx::{migrate::{Migrate, Migrator}, postgres::PgPoolOptions, PgPool};
+use storage::{reconcile, Storage};
+use transaction::{spawn_reservation_expiry, CancellationPolicy, ReservationPolicy};
+use tracing::info;
+use utils::{error, logging};
+use utils::session_store::{spawn_session_sweeper, MemorySessionStore, PgSessionStore, SessionPolicy, SessionStore};
+
+mod config;
+mod health;
+mod listing;
+mod metrics;
 mod owner;
+mod pagination;
+mod payment;
+mod property_picture;
 mod rent_property;
 mod sale_property;
+mod storage;
+mod transaction;
 mod utils;
 mod user;
 
+/// Schema migrations in `./migrations`, embedded at build time.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
 #[derive(Clone)]
 struct AppState {
     db_pool: PgPool,
-    session_store: Arc<Mutex<HashMap<String, Session>>>
+    session_store: Arc<dyn SessionStore>,
+    session_policy: SessionPolicy,
+    storage: Arc<dyn Storage>,
+    mortgage_policy: MortgagePolicy,
+    payment_provider: Arc<dyn PaymentProvider>,
+    cancellation_policy: CancellationPolicy,
+    reservation_policy: ReservationPolicy,
+    config: Config,
+    metrics: Arc<Metrics>,
+}
+
+/// Records every migration up to `through` as applied without running it,
+/// for databases whose schema was brought that far by hand.
+async fn baseline_migrations(db_pool: &PgPool, through: i64) -> Result<(), String> {
+    let mut conn = db_pool.acquire().await.map_err(|err| err.to_string())?;
+
+    conn.ensure_migrations_table().await.map_err(|err| err.to_string())?;
+    let applied = conn.list_applied_migrations().await.map_err(|err| err.to_string())?;
+
+    for migration in MIGRATOR.iter().filter(|migration| !migration.migration_type.is_down_migration() && migration.version <= through) {
+        if applied.iter().any(|applied| applied.version == migration.version) {
+            continue;
+        }
+
+        // sqlx's own table isn't created by our migrations, so this query
+        // can't be checked at compile time.
+        sqlx::query("INSERT INTO _sqlx_migrations(version, description, success, checksum, execution_time) VALUES($1, $2, TRUE, $3, 0)")
+            .bind(migration.version)
+            .bind(&*migration.description)
+            .bind(&*migration.checksum)
+            .execute(&mut *conn).await.map_err(|err| err.to_string())?;
+
+        info!(version = migration.version, description = %migration.description, "Marked migration as applied");
+    }
+
+    Ok(())
 }
 
 #[get("/")]
@@ -35,32 +90,84 @@ async fn greetings(name: web::Path<String>) -> impl Responder {
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     
-    println!("[{}] Server starting up...", Local::now().to_rfc3339());
-
-    let db_url = env::var("DATABASE_URL").expect("Please provide a database url");
-    let address = env::var("ADDRESS").expect("Please provide address to bind");
-    let port = env::var("PORT").expect("Please provide port to bind");
-    // let cors_socket = env::var("CORS_SOCKET").expect("Please provide cors socket");
+    let config = Config::load().unwrap_or_else(|problems| {
+        eprintln!("Invalid configuration:\n{}", problems);
+        process::exit(1);
+    });
+
+    logging::init(&config);
+
+    // Instead of starting the server, `reconcile-uploads [--delete]` reports
+    // orphaned uploads, and removes them with `--delete`, and
+    // `baseline-migrations <version>` marks migrations as already applied.
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = args.first().map(String::as_str);
+    let baseline_through = match (command, args.get(1).map(|version| version.parse::<i64>())) {
+        (None | Some("reconcile-uploads"), _) => None,
+        (Some("baseline-migrations"), Some(Ok(version))) => Some(version),
+        _ => {
+            eprintln!("Invalid command: {}\nUsage: actix_upload [reconcile-uploads [--delete] | baseline-migrations <version>]", args.join(" "));
+            process::exit(2);
+        },
+    };
 
     let db_pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
+        .max_connections(config.database_max_connections)
+        .connect(&config.database_url)
         .await
         .expect("Failed to create pool");
 
+    let storage = storage::from_config(&config).expect("Failed to configure storage");
+
+    if command == Some("reconcile-uploads") {
+        return reconcile::run(&db_pool, storage.as_ref(), args.iter().any(|arg| arg == "--delete")).await.map_err(io::Error::other);
+    }
+    if let Some(version) = baseline_through {
+        return baseline_migrations(&db_pool, version).await.map_err(io::Error::other);
+    }
+
+    info!("Server starting up...");
+
+    // Set RUN_MIGRATIONS=false when migrations are applied separately.
+    if config.run_migrations {
+        MIGRATOR.run(&db_pool).await.expect("Failed to run database migrations");
+        info!("Database migrations up to date");
+    }
+
+    let session_policy = SessionPolicy::from_config(&config);
+    let session_store: Arc<dyn SessionStore> = match config.session_store.as_str() {
+        "memory" => Arc::new(MemorySessionStore::new(session_policy)),
+        _ => Arc::new(PgSessionStore::new(db_pool.clone(), session_policy)),
+    };
+
+    spawn_session_sweeper(session_store.clone(), Duration::from_secs(config.session_sweep_interval_secs));
+    spawn_reservation_expiry(db_pool.clone(), Duration::from_secs(config.reservation_sweep_interval_secs));
+
+    let payment_provider = payment::from_config(&config).expect("Failed to configure payment provider");
+
     let shared_state = AppState {
         db_pool: db_pool.clone(),
-        session_store: Arc::new(Mutex::new(HashMap::new())),
+        session_store,
+        session_policy,
+        storage,
+        mortgage_policy: MortgagePolicy::from_config(&config),
+        payment_provider,
+        cancellation_policy: CancellationPolicy::from_config(&config),
+        reservation_policy: ReservationPolicy::from_config(&config),
+        config: config.clone(),
+        metrics: Arc::new(Metrics::new().expect("Failed to register metrics")),
     };
 
     let app_state = web::Data::new(shared_state);
+    let upload_dir = PathBuf::from(&config.upload_dir);
+    let bind_address = (config.address.clone(), config.port);
+    let shutdown_timeout = config.shutdown_timeout_secs;
 
 
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("http://127.0.0.1:5500")
-            .allowed_origin("https://renoob21.github.io")
-            .allowed_methods(vec!["GET", "POST", "OPTIONS"])
+        let cors = config.cors_origins.iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(vec!["GET", "POST", "PATCH", "DELETE", "OPTIONS"])
             .allowed_headers(vec![
                 http::header::CONTENT_TYPE,
                 http::header::AUTHORIZATION,
@@ -76,15 +183,37 @@ async fn main() -> std::io::Result<()> {
             .service(hello)
             .service(greetings)
             .app_data(app_state.clone())
+            .app_data(MultipartFormConfig::default()
+                .total_limit(config.max_upload_bytes)
+                .error_handler(property_picture::multipart_error))
+            .app_data(web::JsonConfig::default().error_handler(error::json_error))
+            .app_data(web::QueryConfig::default().error_handler(error::query_error))
+            .app_data(web::PathConfig::default().error_handler(error::path_error))
+            .configure(health::init_routes)
+            .configure(metrics::init_routes)
             .configure(owner::init_routes)
+            .configure(payment::init_routes)
+            .configure(|cfg| if config.payment_mock_endpoint { payment::init_mock_routes(cfg) })
+            .configure(property_picture::init_routes)
             .configure(rent_property::init_routes)
             .configure(sale_property::init_routes)
             .configure(user::init_routes)
-            .service(Files::new("/rent-pictures", "./uploaded/rents"))
-            .service(Files::new("/sale-pictures", "./uploaded/sales"))
+            .configure(storage::init_routes)
+            .service(Files::new("/rent-pictures", upload_dir.join("rents")))
+            .service(Files::new("/sale-pictures", upload_dir.join("sales")))
             .wrap(cors)
+            .wrap(from_fn(metrics::track_requests))
+            .wrap(from_fn(logging::request_logger))
     })
-    .bind((address, str::parse::<u16>(&port).unwrap()))?
+    .bind(bind_address)?
+    // On SIGTERM or Ctrl-C, stop accepting connections and give in-flight
+    // requests this long to finish.
+    .shutdown_timeout(shutdown_timeout)
     .run()
-    .await
+    .await?;
+
+    info!("Server stopped");
+    db_pool.close().await;
+
+    Ok(())
 }
\ No newline at end of file
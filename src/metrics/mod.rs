@@ -0,0 +1,141 @@
+use std::time::Instant;
+
+use actix_web::{body::MessageBody, dev::{ServiceRequest, ServiceResponse}, get, middleware::Next, web::{self, ServiceConfig}, Error, HttpResponse};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use tracing::warn;
+
+use crate::{property_picture::PropertyKind, transaction::TransactionKind, utils::error::AppError, AppState};
+
+/// Counters and gauges exposed in the Prometheus text format at `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_connections: IntGaugeVec,
+    sessions: IntGauge,
+    uploaded_files: IntCounterVec,
+    uploaded_bytes: IntCounterVec,
+    transactions_created: IntCounterVec,
+    payments_settled: IntCounterVec,
+}
+
+fn property_label(kind: PropertyKind) -> &'static str {
+    match kind {
+        PropertyKind::Rent => "rent",
+        PropertyKind::Sale => "sale",
+    }
+}
+
+fn transaction_label(kind: TransactionKind) -> &'static str {
+    match kind {
+        TransactionKind::Rent => "rent",
+        TransactionKind::Sale => "sale",
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, String> {
+        let registry = Registry::new_custom(Some("actix_upload".to_string()), None).map_err(|err| err.to_string())?;
+
+        let counter = |name: &str, help: &str, labels: &[&str]| -> Result<IntCounterVec, String> {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels).map_err(|err| err.to_string())?;
+            registry.register(Box::new(counter.clone())).map_err(|err| err.to_string())?;
+            Ok(counter)
+        };
+
+        let http_requests = counter("http_requests_total", "Requests handled, by route pattern and status", &["method", "route", "status"])?;
+        let uploaded_files = counter("uploaded_files_total", "Pictures uploaded, by property kind", &["kind"])?;
+        let uploaded_bytes = counter("uploaded_bytes_total", "Bytes of pictures uploaded, by property kind", &["kind"])?;
+        let transactions_created = counter("transactions_created_total", "Transactions created, by kind", &["kind"])?;
+        let payments_settled = counter("payments_settled_total", "Payments settled by the provider, by transaction kind and outcome", &["kind", "status"])?;
+
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time taken to handle requests, by route pattern"),
+            &["method", "route"]
+        ).map_err(|err| err.to_string())?;
+        registry.register(Box::new(http_request_duration.clone())).map_err(|err| err.to_string())?;
+
+        let db_connections = IntGaugeVec::new(Opts::new("db_pool_connections", "Database pool connections, by state"), &["state"])
+            .map_err(|err| err.to_string())?;
+        registry.register(Box::new(db_connections.clone())).map_err(|err| err.to_string())?;
+
+        let sessions = IntGauge::new("sessions", "Sessions that have not expired").map_err(|err| err.to_string())?;
+        registry.register(Box::new(sessions.clone())).map_err(|err| err.to_string())?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_connections,
+            sessions,
+            uploaded_files,
+            uploaded_bytes,
+            transactions_created,
+            payments_settled,
+        })
+    }
+
+    pub fn record_upload(&self, kind: PropertyKind, files: usize, bytes: usize) {
+        self.uploaded_files.with_label_values(&[property_label(kind)]).inc_by(files as u64);
+        self.uploaded_bytes.with_label_values(&[property_label(kind)]).inc_by(bytes as u64);
+    }
+
+    pub fn record_transaction(&self, kind: TransactionKind) {
+        self.transactions_created.with_label_values(&[transaction_label(kind)]).inc();
+    }
+
+    /// `transaction_kind` and `status` are as stored on the payment.
+    pub fn record_payment(&self, transaction_kind: &str, status: &str) {
+        self.payments_settled.with_label_values(&[&transaction_kind.to_lowercase(), &status.to_lowercase()]).inc();
+    }
+
+    fn observe_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        self.http_requests.with_label_values(&[method, route, &status.to_string()]).inc();
+        self.http_request_duration.with_label_values(&[method, route]).observe(seconds);
+    }
+}
+
+/// Counts requests and times them. Requests are labelled by the pattern of
+/// the route they matched, such as `/api/rent-property/{id}`, so the number of
+/// series stays bounded.
+pub async fn track_requests(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let app_state = req.app_data::<web::Data<AppState>>().cloned();
+    let method = req.method().to_string();
+    let started = Instant::now();
+
+    let res = next.call(req).await?;
+
+    if let Some(app_state) = app_state {
+        let route = res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        app_state.metrics.observe_request(&method, &route, res.status().as_u16(), started.elapsed().as_secs_f64());
+    }
+
+    Ok(res)
+}
+
+#[get("/metrics")]
+async fn get_metrics(app_state: web::Data<AppState>) -> Result<HttpResponse, AppError> {
+    let metrics = &app_state.metrics;
+    let pool = &app_state.db_pool;
+    let idle = pool.num_idle() as i64;
+
+    metrics.db_connections.with_label_values(&["idle"]).set(idle);
+    metrics.db_connections.with_label_values(&["active"]).set(pool.size() as i64 - idle);
+    metrics.db_connections.with_label_values(&["max"]).set(app_state.config.database_max_connections as i64);
+
+    match app_state.session_store.count().await {
+        Ok(count) => metrics.sessions.set(count as i64),
+        Err(err) => warn!(error = %err, "Failed counting sessions"),
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder.encode(&metrics.registry.gather(), &mut body)
+        .map_err(|err| AppError::Internal(format!("Failed encoding metrics: {}", err)))?;
+
+    Ok(HttpResponse::Ok().content_type(encoder.format_type()).body(body))
+}
+
+pub fn init_routes(cfg: &mut ServiceConfig) {
+    cfg.service(get_metrics);
+}
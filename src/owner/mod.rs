@@ -1,56 +1,174 @@
-use actix_web::{get, web::{self, ServiceConfig}, HttpResponse, Responder};
+use actix_web::{get, post, web::{self, ServiceConfig}, HttpResponse};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, types::Uuid};
+use sqlx::{prelude::FromRow, types::Uuid, PgPool};
 
-use crate::{utils::models::ApiResponse, AppState};
+use crate::{pagination::{Paginated, Pagination, SortColumn, SortKey, SortValue}, user::Role, utils::{auth::AuthSession, error::{AppError, OrNotFound}, models::{ApiResponse, Session}}, AppState};
 
 #[derive(Debug, Deserialize, Serialize, FromRow)]
 pub struct Owner {
-    owner_id: Uuid,
+    pub owner_id: Uuid,
     owner_name: String,
     address: String,
-    email: String
+    email: String,
+    user_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
 }
 
-#[get("/api/owner")]
-async fn get_owners(app_state: web::Data<AppState>) -> impl Responder {
-    let result = sqlx::query_as!(
+impl Paginated for Owner {
+    const ID_COLUMN: &'static str = "owner_id";
+
+    fn sort_column(key: SortKey) -> Option<SortColumn> {
+        match key {
+            SortKey::Newest => Some(SortColumn::Time("created_at")),
+            SortKey::Price | SortKey::Size => None,
+        }
+    }
+
+    fn position(&self, _: SortKey) -> (SortValue, Uuid) {
+        (SortValue::Time(self.created_at), self.owner_id)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OwnerForm {
+    owner_name: Option<String>,
+    address: Option<String>,
+    email: Option<String>,
+}
+
+pub async fn get_owner_by_user(db_pool: &PgPool, user_id: Uuid) -> sqlx::Result<Option<Owner>> {
+    sqlx::query_as!(
         Owner,
-        "SELECT * FROM property_owner"
-    ).fetch_all(&app_state.db_pool).await;
+        "SELECT * FROM property_owner WHERE user_id = $1",
+        user_id
+    ).fetch_optional(db_pool).await
+}
 
+/// Decides which owner a new listing belongs to. Listings default to the
+/// caller's own owner profile; only admins may pick another owner through
+/// the `requested` form field.
+pub async fn resolve_listing_owner(db_pool: &PgPool, session: &Session, requested: Option<&str>) -> Result<Uuid, AppError> {
+    if session.user_data.role == Role::Admin {
+        if let Some(owner) = requested {
+            return Uuid::parse_str(owner).map_err(|_| AppError::BadRequest("Error: Invalid UUID format on owner_id".to_string()));
+        }
+    }
 
-    match result {
-        Ok(owners) => HttpResponse::Ok().json(
-            ApiResponse::new(true, "Successfully fetched owner list".to_string(), Some(owners), None)
-        ),
-        Err(err) => HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed fetching owner list".to_string(), None, Some(err.to_string()))
-        )
+    match get_owner_by_user(db_pool, session.user_data.user_id).await? {
+        Some(owner) => Ok(owner.owner_id),
+        None => Err(AppError::Forbidden("Register as an owner through POST /api/owner first".to_string())),
     }
-    
+}
+
+/// Checks that the caller is the owner `owner_id` belongs to, or an admin.
+pub async fn authorize_listing_owner(db_pool: &PgPool, session: &Session, owner_id: Uuid) -> Result<(), AppError> {
+    if session.user_data.role == Role::Admin {
+        return Ok(());
+    }
+
+    match get_owner_by_user(db_pool, session.user_data.user_id).await? {
+        Some(owner) if owner.owner_id == owner_id => Ok(()),
+        _ => Err(AppError::Forbidden("User does not own this property".to_string())),
+    }
+}
+
+/// Creates the caller's owner profile. An unlinked profile carrying the
+/// caller's account email, such as one from before owners had accounts, is
+/// claimed instead, keeping its listings; fields sent in the form replace
+/// its name and address.
+#[post("/api/owner")]
+async fn register_owner(app_state: web::Data<AppState>, session: AuthSession, owner_form: web::Json<OwnerForm>) -> Result<HttpResponse, AppError> {
+    let user_data = &session.user_data;
+
+    if get_owner_by_user(&app_state.db_pool, user_data.user_id).await?.is_some() {
+        return Err(AppError::AlreadyExists("User already has an owner profile".to_string()));
+    }
+
+    let owner_form = owner_form.into_inner();
+    let email = owner_form.email.unwrap_or_else(|| user_data.email_address.clone());
+
+    let mut trx = app_state.db_pool.begin().await?;
+
+    let claimed = sqlx::query_as!(
+        Owner,
+        "UPDATE property_owner SET user_id = $1, owner_name = COALESCE($3, owner_name), address = COALESCE($4, address)
+            WHERE email = $2 AND user_id IS NULL RETURNING *",
+        user_data.user_id,
+        user_data.email_address,
+        owner_form.owner_name,
+        owner_form.address
+    ).fetch_optional(&mut *trx).await;
+
+    let result = match claimed {
+        Ok(Some(owner)) => Ok(owner),
+        Ok(None) => sqlx::query_as!(
+            Owner,
+            "INSERT INTO property_owner(owner_id, owner_name, address, email, user_id)
+                VALUES($1, $2, $3, $4, $5) RETURNING *",
+            Uuid::new_v4(),
+            owner_form.owner_name.unwrap_or_else(|| user_data.full_name.clone()),
+            owner_form.address.unwrap_or_else(|| user_data.address.clone()),
+            email,
+            user_data.user_id
+        ).fetch_one(&mut *trx).await,
+        Err(err) => Err(err),
+    };
+
+    // The check above can race another request from the same user, which
+    // trips the unique index on user_id rather than the one on email.
+    let owner = match result {
+        Err(sqlx::Error::Database(db_err)) if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation => {
+            return Err(AppError::AlreadyExists(match db_err.constraint() {
+                Some("property_owner_user_id_key") => "User already has an owner profile".to_string(),
+                _ => format!("Email: {} already registered as owner", email),
+            }));
+        },
+        result => result?,
+    };
+
+    // Buyers become owners; admins keep their role.
+    sqlx::query!(
+        "UPDATE \"user\" SET role = 'owner' WHERE user_id = $1 AND role = 'buyer'",
+        user_data.user_id
+    ).execute(&mut *trx).await?;
+
+    trx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully registered owner".to_string(), Some(owner), None)
+    ))
+}
+
+#[get("/api/owner")]
+async fn get_owners(app_state: web::Data<AppState>, page: Pagination<Owner>) -> Result<HttpResponse, AppError> {
+    let owners = page.fetch(&app_state.db_pool, |query| {
+        query.push("SELECT * FROM property_owner");
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully fetched owner list".to_string(), Some(owners), None)
+    ))
 }
 
 #[get("/api/owner/{owner_id}")]
-async fn get_owner_by_id(app_state: web::Data<AppState>, owner_id: web::Path<Uuid>) -> impl Responder {
-    let result = sqlx::query_as!(
+async fn get_owner_by_id(app_state: web::Data<AppState>, owner_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let owner_id = owner_id.into_inner();
+
+    let owner = sqlx::query_as!(
         Owner,
         "SELECT * FROM property_owner WHERE owner_id = $1",
-        owner_id.into_inner()
-    ).fetch_one(&app_state.db_pool).await;
-
-    match result {
-        Ok(owner) => HttpResponse::Ok().json(
-            ApiResponse::new(true, "Successfully fetched owner data".to_string(), Some(owner), None)
-        ),
-        Err(err) => HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed fetching owner data".to_string(), None, Some(err.to_string()))
-        )
-    }
+        owner_id
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No owner matching id: {}", owner_id))?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully fetched owner data".to_string(), Some(owner), None)
+    ))
 }
 
 pub fn init_routes(cfg: &mut ServiceConfig) {
     cfg
+        .service(register_owner)
         .service(get_owners)
         .service(get_owner_by_id);
-}
\ No newline at end of file
+}
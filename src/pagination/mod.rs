@@ -0,0 +1,368 @@
+use std::{future::{ready, Ready}, marker::PhantomData};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, FromRow, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::utils::error::AppError;
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Newest,
+    Price,
+    Size,
+}
+
+impl SortKey {
+    fn name(&self) -> &'static str {
+        match self {
+            SortKey::Newest => "newest",
+            SortKey::Price => "price",
+            SortKey::Size => "size",
+        }
+    }
+
+    /// Newest first; cheapest and smallest first.
+    fn default_order(&self) -> SortOrder {
+        match self {
+            SortKey::Newest => SortOrder::Desc,
+            SortKey::Price | SortKey::Size => SortOrder::Asc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Value of the sorted column on a row.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortValue {
+    Number(i64),
+    Date(NaiveDate),
+    Time(DateTime<Utc>),
+}
+
+/// A sortable column, named by the type of its values.
+#[derive(Debug, Clone, Copy)]
+pub enum SortColumn {
+    Number(&'static str),
+    Date(&'static str),
+    Time(&'static str),
+}
+
+impl SortColumn {
+    fn name(&self) -> &'static str {
+        match self {
+            SortColumn::Number(name) | SortColumn::Date(name) | SortColumn::Time(name) => name,
+        }
+    }
+
+    /// Whether `value` can be compared with this column.
+    fn holds(&self, value: &SortValue) -> bool {
+        matches!(
+            (self, value),
+            (SortColumn::Number(_), SortValue::Number(_)) | (SortColumn::Date(_), SortValue::Date(_)) | (SortColumn::Time(_), SortValue::Time(_))
+        )
+    }
+}
+
+/// A row type that lists can be paginated over.
+pub trait Paginated: for<'r> FromRow<'r, PgRow> + Send + Unpin {
+    /// Column breaking ties between rows with equal sort values.
+    const ID_COLUMN: &'static str;
+
+    /// Column sorted on for `key`, or `None` if the list can't be sorted that way.
+    fn sort_column(key: SortKey) -> Option<SortColumn>;
+
+    /// Sort value and id of this row, used to resume after it.
+    fn position(&self, key: SortKey) -> (SortValue, Uuid);
+}
+
+/// Where the next page starts. Clients receive it base64 encoded and send
+/// it back as is.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: SortKey,
+    order: SortOrder,
+    value: SortValue,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor is always serializable"))
+    }
+
+    fn decode(raw: &str) -> Result<Cursor, String> {
+        URL_SAFE_NO_PAD.decode(raw).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or("Error: Malformed cursor".to_string())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PageQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<SortKey>,
+    order: Option<SortOrder>,
+}
+
+/// One page of a list.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Rows across all pages.
+    pub total: i64,
+    pub limit: i64,
+    pub offset: Option<i64>,
+    /// Pass as `cursor` to get the following page. Absent on the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn with_items<U>(self, items: Vec<U>) -> Page<U> {
+        Page { items, total: self.total, limit: self.limit, offset: self.offset, next_cursor: self.next_cursor }
+    }
+}
+
+/// Paging and sorting read from the query string: `limit`, either `offset`
+/// or a `cursor` from a previous page, `sort` (`newest`, `price`, `size`)
+/// and `order` (`asc`, `desc`).
+pub struct Pagination<T> {
+    pub limit: i64,
+    pub offset: i64,
+    pub sort: SortKey,
+    pub order: SortOrder,
+    sort_column: SortColumn,
+    after: Option<Cursor>,
+    rows: PhantomData<fn() -> T>,
+}
+
+impl<T: Paginated> Pagination<T> {
+    fn from_query(query_string: &str) -> Result<Self, String> {
+        let query = web::Query::<PageQuery>::from_query(query_string).map_err(|err| err.to_string())?.into_inner();
+
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(format!("Error: limit must be between 1 and {}", MAX_LIMIT));
+        }
+
+        let offset = query.offset.unwrap_or(0);
+        if offset < 0 {
+            return Err("Error: offset must not be negative".to_string());
+        }
+
+        let after = match query.cursor.as_deref() {
+            Some(raw) => Some(Cursor::decode(raw)?),
+            None => None,
+        };
+
+        // A cursor only makes sense under the ordering it was issued for.
+        let (sort, order) = match &after {
+            Some(_) if query.offset.is_some() => return Err("Error: Use either offset or cursor, not both".to_string()),
+            Some(cursor) if query.sort.is_some_and(|sort| sort != cursor.sort) || query.order.is_some_and(|order| order != cursor.order) => {
+                return Err("Error: sort and order must match the cursor".to_string());
+            },
+            Some(cursor) => (cursor.sort, cursor.order),
+            None => {
+                let sort = query.sort.unwrap_or(SortKey::Newest);
+                (sort, query.order.unwrap_or(sort.default_order()))
+            },
+        };
+
+        let sort_column = T::sort_column(sort).ok_or(format!("Error: This list cannot be sorted by {}", sort.name()))?;
+        if after.as_ref().is_some_and(|cursor| !sort_column.holds(&cursor.value)) {
+            return Err("Error: Malformed cursor".to_string());
+        }
+
+        Ok(Pagination { limit, offset, sort, order, sort_column, after, rows: PhantomData })
+    }
+
+    /// Fetches one page from the rows selected by `source`, which must select
+    /// the sort and id columns of `T`.
+    pub async fn fetch(&self, db_pool: &PgPool, source: impl Fn(&mut QueryBuilder<'_, Postgres>)) -> sqlx::Result<Page<T>> {
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM (");
+        source(&mut count);
+        count.push(") AS page_source");
+
+        let total: i64 = count.build_query_scalar().fetch_one(db_pool).await?;
+
+        let mut query = QueryBuilder::new("SELECT * FROM (");
+        source(&mut query);
+        query.push(") AS page_source");
+
+        let direction = match self.order {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+
+        if let Some(after) = &self.after {
+            let comparison = match self.order {
+                SortOrder::Asc => ">",
+                SortOrder::Desc => "<",
+            };
+
+            query.push(format!(" WHERE ({}, {}) {} (", self.sort_column.name(), T::ID_COLUMN, comparison));
+            match after.value {
+                SortValue::Number(value) => query.push_bind(value),
+                SortValue::Date(value) => query.push_bind(value),
+                SortValue::Time(value) => query.push_bind(value),
+            };
+            query.push(", ").push_bind(after.id).push(")");
+        }
+
+        query.push(format!(" ORDER BY {} {}, {} {}", self.sort_column.name(), direction, T::ID_COLUMN, direction));
+
+        // One extra row tells whether another page follows.
+        query.push(" LIMIT ").push_bind(self.limit + 1);
+        if self.after.is_none() {
+            query.push(" OFFSET ").push_bind(self.offset);
+        }
+
+        let mut items: Vec<T> = query.build_query_as().fetch_all(db_pool).await?;
+
+        let next_cursor = if items.len() as i64 > self.limit {
+            items.truncate(self.limit as usize);
+            items.last().map(|last| {
+                let (value, id) = last.position(self.sort);
+                Cursor { sort: self.sort, order: self.order, value, id }.encode()
+            })
+        } else {
+            None
+        };
+
+        Ok(Page {
+            items,
+            total,
+            limit: self.limit,
+            offset: if self.after.is_none() { Some(self.offset) } else { None },
+            next_cursor,
+        })
+    }
+}
+
+impl<T: Paginated> FromRequest for Pagination<T> {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::from_query(req.query_string()).map_err(AppError::BadRequest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[derive(FromRow)]
+    struct Row {
+        id: Uuid,
+        price: i64,
+        created_at: DateTime<Utc>,
+    }
+
+    impl Paginated for Row {
+        const ID_COLUMN: &'static str = "id";
+
+        fn sort_column(key: SortKey) -> Option<SortColumn> {
+            match key {
+                SortKey::Newest => Some(SortColumn::Time("created_at")),
+                SortKey::Price => Some(SortColumn::Number("price")),
+                SortKey::Size => None,
+            }
+        }
+
+        fn position(&self, key: SortKey) -> (SortValue, Uuid) {
+            match key {
+                SortKey::Price => (SortValue::Number(self.price), self.id),
+                _ => (SortValue::Time(self.created_at), self.id),
+            }
+        }
+    }
+
+    async fn extract(query: &str) -> Result<Pagination<Row>, AppError> {
+        let req = TestRequest::get().uri(&format!("/rows?{}", query)).to_http_request();
+
+        Pagination::<Row>::from_request(&req, &mut Payload::None).await
+    }
+
+    fn raw_cursor(json: serde_json::Value) -> String {
+        URL_SAFE_NO_PAD.encode(json.to_string())
+    }
+
+    fn assert_bad_request(result: Result<Pagination<Row>, AppError>, detail: &str) {
+        match result {
+            Err(AppError::BadRequest(error)) => assert_eq!(error, detail),
+            Err(err) => panic!("expected BadRequest, got {:?}", err),
+            Ok(_) => panic!("expected BadRequest, got a pagination"),
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let row = Row { id: Uuid::new_v4(), price: 1_500, created_at: Utc::now() };
+
+        for (sort, order) in [(SortKey::Price, SortOrder::Asc), (SortKey::Newest, SortOrder::Desc)] {
+            let (value, id) = row.position(sort);
+            let decoded = Cursor::decode(&Cursor { sort, order, value: value.clone(), id }.encode()).unwrap();
+
+            assert_eq!((decoded.sort, decoded.order, decoded.id), (sort, order, id));
+            assert_eq!(serde_json::to_value(&decoded.value).unwrap(), serde_json::to_value(&value).unwrap());
+        }
+    }
+
+    #[actix_web::test]
+    async fn cursor_resumes_its_ordering() {
+        let cursor = Cursor { sort: SortKey::Price, order: SortOrder::Desc, value: SortValue::Number(10), id: Uuid::new_v4() };
+        let page = extract(&format!("cursor={}", cursor.encode())).await.unwrap();
+
+        assert_eq!((page.sort, page.order), (SortKey::Price, SortOrder::Desc));
+        assert!(matches!(page.after, Some(Cursor { value: SortValue::Number(10), .. })));
+    }
+
+    #[actix_web::test]
+    async fn cursor_value_must_match_the_sort_column() {
+        let id = Uuid::new_v4();
+
+        let string_value = raw_cursor(serde_json::json!({ "sort": "price", "order": "asc", "value": { "string": "cheap" }, "id": id }));
+        assert_bad_request(extract(&format!("cursor={}", string_value)).await, "Error: Malformed cursor");
+
+        let time_value = raw_cursor(serde_json::json!({ "sort": "price", "order": "asc", "value": { "time": "2025-01-01T00:00:00Z" }, "id": id }));
+        assert_bad_request(extract(&format!("cursor={}", time_value)).await, "Error: Malformed cursor");
+
+        let number_value = raw_cursor(serde_json::json!({ "sort": "newest", "order": "desc", "value": { "number": 1 }, "id": id }));
+        assert_bad_request(extract(&format!("cursor={}", number_value)).await, "Error: Malformed cursor");
+    }
+
+    #[actix_web::test]
+    async fn undecodable_cursor_is_rejected() {
+        assert_bad_request(extract("cursor=not*base64").await, "Error: Malformed cursor");
+
+        let not_json = URL_SAFE_NO_PAD.encode("not json");
+        assert_bad_request(extract(&format!("cursor={}", not_json)).await, "Error: Malformed cursor");
+    }
+
+    #[actix_web::test]
+    async fn cursor_excludes_offset_and_other_orderings() {
+        let cursor = Cursor { sort: SortKey::Price, order: SortOrder::Asc, value: SortValue::Number(10), id: Uuid::new_v4() }.encode();
+
+        assert_bad_request(extract(&format!("cursor={}&offset=20", cursor)).await, "Error: Use either offset or cursor, not both");
+        assert_bad_request(extract(&format!("cursor={}&sort=newest", cursor)).await, "Error: sort and order must match the cursor");
+    }
+}
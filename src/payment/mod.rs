@@ -0,0 +1,410 @@
+use std::sync::Arc;
+
+use actix_web::{post, web::{self, ServiceConfig}, HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{prelude::FromRow, PgConnection};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{config::Config, rent_property::confirm_rent_payment, sale_property::{confirm_down_payment, confirm_installment_payment}, utils::{auth::AdminSession, error::AppError, models::ApiResponse}, AppState};
+
+/// What a payment settles.
+#[derive(Debug, Clone, Copy)]
+pub enum PaymentTarget {
+    Rent(Uuid),
+    SaleDownPayment(Uuid),
+    SaleInstallment(Uuid, i32),
+}
+
+impl PaymentTarget {
+    fn columns(&self) -> (&'static str, Uuid, Option<i32>) {
+        match *self {
+            PaymentTarget::Rent(id) => ("Rent", id, None),
+            PaymentTarget::SaleDownPayment(id) => ("Sale", id, None),
+            PaymentTarget::SaleInstallment(id, n) => ("Sale", id, Some(n)),
+        }
+    }
+}
+
+/// One attempt at paying a transaction. `status` is `Pending` until the
+/// provider's webhook reports it `Succeeded` or `Failed`.
+#[derive(Debug, Serialize, FromRow)]
+pub struct Payment {
+    pub payment_id: Uuid,
+    pub transaction_kind: String,
+    pub transaction_id: Uuid,
+    pub installment_number: Option<i32>,
+    pub user_id: Uuid,
+    pub amount: i64,
+    pub provider: String,
+    pub provider_reference: Option<String>,
+    /// Where the user completes the payment, if the provider has such a page.
+    pub checkout_url: Option<String>,
+    pub status: String,
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub settled_at: Option<DateTime<Utc>>,
+    /// Owed back when the payment succeeded after what it paid for was
+    /// cancelled.
+    pub refund_amount: Option<i64>,
+}
+
+impl Payment {
+    fn target(&self) -> PaymentTarget {
+        match (self.transaction_kind.as_str(), self.installment_number) {
+            ("Rent", _) => PaymentTarget::Rent(self.transaction_id),
+            (_, None) => PaymentTarget::SaleDownPayment(self.transaction_id),
+            (_, Some(n)) => PaymentTarget::SaleInstallment(self.transaction_id, n),
+        }
+    }
+}
+
+/// A payment the provider is waiting on.
+#[derive(Debug)]
+pub struct PaymentIntent {
+    pub provider_reference: String,
+    pub checkout_url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PaymentOutcome {
+    Succeeded,
+    Failed(String),
+}
+
+/// Result of a payment, as reported by the provider.
+#[derive(Debug, Clone)]
+pub struct PaymentEvent {
+    pub provider_reference: String,
+    pub outcome: PaymentOutcome,
+}
+
+/// A payment gateway. Money moves on the provider's side; it reports back
+/// through the webhook.
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn create_intent(&self, payment: &Payment) -> Result<PaymentIntent, String>;
+
+    /// Checks the webhook's signature and reads the event it carries.
+    fn parse_webhook(&self, req: &HttpRequest, body: &[u8]) -> Result<PaymentEvent, String>;
+}
+
+/// Builds the provider selected by `config.payment_provider`. Only `mock`
+/// exists so far.
+pub fn from_config(config: &Config) -> Result<Arc<dyn PaymentProvider>, String> {
+    match config.payment_provider.as_str() {
+        "mock" => Ok(Arc::new(MockPaymentProvider::new(config.payment_webhook_secret.as_ref().map(|secret| secret.expose().to_string())))),
+        other => Err(format!("Unknown payment provider: {}", other)),
+    }
+}
+
+const SIGNATURE_HEADER: &str = "X-Payment-Signature";
+
+#[derive(Debug, Deserialize)]
+struct MockWebhook {
+    reference: String,
+    status: String,
+    reason: Option<String>,
+}
+
+/// Accepts every payment without moving money. Payments are completed by
+/// posting a webhook signed with `payment_webhook_secret`, or by an admin
+/// through `POST /api/payments/mock/{reference}/{outcome}` when
+/// `payment_mock_endpoint` is set.
+pub struct MockPaymentProvider {
+    secret: String,
+}
+
+impl MockPaymentProvider {
+    /// Without a secret a random one is used, so only the mock endpoint can
+    /// complete payments.
+    pub fn new(secret: Option<String>) -> Self {
+        MockPaymentProvider { secret: secret.unwrap_or_else(|| Uuid::new_v4().to_string()) }
+    }
+
+    fn mac(&self, body: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(body);
+        mac
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for MockPaymentProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn create_intent(&self, _: &Payment) -> Result<PaymentIntent, String> {
+        Ok(PaymentIntent { provider_reference: format!("mock_{}", Uuid::new_v4().simple()), checkout_url: None })
+    }
+
+    /// Expects the hex HMAC-SHA256 of the body in `X-Payment-Signature` and a
+    /// body of `{"reference", "status": "succeeded" | "failed", "reason"}`.
+    fn parse_webhook(&self, req: &HttpRequest, body: &[u8]) -> Result<PaymentEvent, String> {
+        let signature = req.headers().get(SIGNATURE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| hex::decode(value).ok())
+            .ok_or(format!("Error: Missing or malformed {} header", SIGNATURE_HEADER))?;
+
+        self.mac(body).verify_slice(&signature).map_err(|_| "Error: Invalid signature".to_string())?;
+
+        let webhook: MockWebhook = serde_json::from_slice(body).map_err(|err| err.to_string())?;
+        let outcome = match webhook.status.as_str() {
+            "succeeded" => PaymentOutcome::Succeeded,
+            "failed" => PaymentOutcome::Failed(webhook.reason.unwrap_or("Payment failed".to_string())),
+            other => return Err(format!("Error: Unknown payment status {}", other)),
+        };
+
+        Ok(PaymentEvent { provider_reference: webhook.reference, outcome })
+    }
+}
+
+/// Opens a payment for `target` with the provider, or returns the one still
+/// pending for it. Callers hold a lock on the transaction being paid.
+pub async fn start_payment(provider: &dyn PaymentProvider, conn: &mut PgConnection, user_id: Uuid, target: PaymentTarget, amount: i64) -> Result<Payment, AppError> {
+    let (transaction_kind, transaction_id, installment_number) = target.columns();
+
+    let pending = sqlx::query_as!(
+        Payment,
+        "SELECT * FROM payment WHERE transaction_kind = $1 AND transaction_id = $2 AND installment_number IS NOT DISTINCT FROM $3 AND status = 'Pending'",
+        transaction_kind,
+        transaction_id,
+        installment_number
+    ).fetch_optional(&mut *conn).await?;
+
+    if let Some(payment) = pending {
+        return Ok(payment);
+    }
+
+    let payment = sqlx::query_as!(
+        Payment,
+        "INSERT INTO payment(payment_id, transaction_kind, transaction_id, installment_number, user_id, amount, provider)
+        VALUES($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+        Uuid::new_v4(),
+        transaction_kind,
+        transaction_id,
+        installment_number,
+        user_id,
+        amount,
+        provider.name()
+    ).fetch_one(&mut *conn).await?;
+
+    let intent = provider.create_intent(&payment).await.map_err(AppError::Upstream)?;
+
+    sqlx::query_as!(
+        Payment,
+        "UPDATE payment SET provider_reference = $2, checkout_url = $3 WHERE payment_id = $1 RETURNING *",
+        payment.payment_id,
+        intent.provider_reference,
+        intent.checkout_url
+    ).fetch_one(&mut *conn).await.map_err(AppError::from)
+}
+
+/// Records the outcome of a payment and, when it succeeded, marks what it
+/// paid for as paid. If that no longer accepts payment, for example because
+/// it was cancelled meanwhile, the whole amount is recorded as owed back.
+/// Events for payments already settled change nothing, so providers may
+/// deliver them more than once. Returns the payment and whether this event
+/// settled it, or `None` for unknown references.
+pub async fn settle_payment(conn: &mut PgConnection, event: &PaymentEvent) -> sqlx::Result<Option<(Payment, bool)>> {
+    let payment = match sqlx::query_as!(
+        Payment,
+        "SELECT * FROM payment WHERE provider_reference = $1 FOR UPDATE",
+        event.provider_reference
+    ).fetch_optional(&mut *conn).await? {
+        Some(payment) if payment.status == "Pending" => payment,
+        other => return Ok(other.map(|payment| (payment, false))),
+    };
+
+    let (status, failure_reason) = match &event.outcome {
+        PaymentOutcome::Succeeded => ("Succeeded", None),
+        PaymentOutcome::Failed(reason) => ("Failed", Some(reason.clone())),
+    };
+
+    let payment = sqlx::query_as!(
+        Payment,
+        "UPDATE payment SET status = $2, failure_reason = $3, settled_at = NOW() WHERE payment_id = $1 RETURNING *",
+        payment.payment_id,
+        status,
+        failure_reason
+    ).fetch_one(&mut *conn).await?;
+
+    if status != "Succeeded" {
+        return Ok(Some((payment, true)));
+    }
+
+    let applied = match payment.target() {
+        PaymentTarget::Rent(id) => confirm_rent_payment(conn, id).await?,
+        PaymentTarget::SaleDownPayment(id) => confirm_down_payment(conn, id).await?,
+        PaymentTarget::SaleInstallment(id, n) => confirm_installment_payment(conn, id, n).await?,
+    };
+
+    if applied {
+        return Ok(Some((payment, true)));
+    }
+
+    warn!(payment_id = %payment.payment_id, transaction_id = %payment.transaction_id, "Payment succeeded but its transaction no longer accepts it, refund owed");
+
+    let payment = sqlx::query_as!(
+        Payment,
+        "UPDATE payment SET refund_amount = amount WHERE payment_id = $1 RETURNING *",
+        payment.payment_id
+    ).fetch_one(&mut *conn).await?;
+
+    Ok(Some((payment, true)))
+}
+
+async fn settle_event(app_state: &AppState, event: &PaymentEvent) -> Result<HttpResponse, AppError> {
+    let mut trx = app_state.db_pool.begin().await?;
+
+    let (payment, settled) = settle_payment(&mut trx, event).await?
+        .ok_or_else(|| AppError::NotFound(format!("Error: No payment matching reference: {}", event.provider_reference)))?;
+
+    trx.commit().await?;
+
+    if settled {
+        app_state.metrics.record_payment(&payment.transaction_kind, &payment.status);
+    }
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Payment settled".to_string(), Some(payment), None)
+    ))
+}
+
+#[post("/api/payments/webhook")]
+async fn payment_webhook(app_state: web::Data<AppState>, req: HttpRequest, body: web::Bytes) -> Result<HttpResponse, AppError> {
+    let event = app_state.payment_provider.parse_webhook(&req, &body).map_err(AppError::Unauthorized)?;
+
+    settle_event(&app_state, &event).await
+}
+
+/// Completes a mock payment, standing in for the provider's webhook during
+/// development. `outcome` is `succeed` or `fail`.
+#[post("/api/payments/mock/{reference}/{outcome}")]
+async fn complete_mock_payment(app_state: web::Data<AppState>, _: AdminSession, path: web::Path<(String, String)>) -> Result<HttpResponse, AppError> {
+    let (provider_reference, outcome) = path.into_inner();
+    let outcome = match outcome.as_str() {
+        "succeed" => PaymentOutcome::Succeeded,
+        "fail" => PaymentOutcome::Failed("Declined by mock provider".to_string()),
+        _ => return Err(AppError::BadRequest("Error: outcome must be succeed or fail".to_string())),
+    };
+
+    settle_event(&app_state, &PaymentEvent { provider_reference, outcome }).await
+}
+
+pub fn init_routes(cfg: &mut ServiceConfig) {
+    cfg.service(payment_webhook);
+}
+
+/// Routes registered only when `payment_mock_endpoint` is set.
+pub fn init_mock_routes(cfg: &mut ServiceConfig) {
+    cfg.service(complete_mock_payment);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use sqlx::PgPool;
+
+    use super::*;
+
+    const BODY: &[u8] = br#"{"reference": "mock_1", "status": "succeeded"}"#;
+
+    fn provider() -> MockPaymentProvider {
+        MockPaymentProvider::new(Some("secret".to_string()))
+    }
+
+    fn signed(provider: &MockPaymentProvider, body: &[u8]) -> HttpRequest {
+        let signature = hex::encode(provider.mac(body).finalize().into_bytes());
+        TestRequest::default().insert_header((SIGNATURE_HEADER, signature)).to_http_request()
+    }
+
+    #[test]
+    fn valid_signature_is_accepted() {
+        let provider = provider();
+        let event = provider.parse_webhook(&signed(&provider, BODY), BODY).unwrap();
+
+        assert_eq!(event.provider_reference, "mock_1");
+        assert!(matches!(event.outcome, PaymentOutcome::Succeeded));
+    }
+
+    #[test]
+    fn failed_payment_carries_its_reason() {
+        let provider = provider();
+        let body = br#"{"reference": "mock_1", "status": "failed", "reason": "Card declined"}"#;
+        let event = provider.parse_webhook(&signed(&provider, body), body).unwrap();
+
+        assert!(matches!(event.outcome, PaymentOutcome::Failed(reason) if reason == "Card declined"));
+    }
+
+    #[test]
+    fn tampered_body_is_rejected() {
+        let provider = provider();
+        let tampered = br#"{"reference": "mock_2", "status": "succeeded"}"#;
+
+        assert_eq!(provider.parse_webhook(&signed(&provider, BODY), tampered).unwrap_err(), "Error: Invalid signature");
+    }
+
+    #[test]
+    fn signature_from_another_secret_is_rejected() {
+        let other = MockPaymentProvider::new(Some("other".to_string()));
+
+        assert!(provider().parse_webhook(&signed(&other, BODY), BODY).is_err());
+    }
+
+    #[test]
+    fn missing_signature_is_rejected() {
+        let req = TestRequest::default().to_http_request();
+
+        assert_eq!(provider().parse_webhook(&req, BODY).unwrap_err(), "Error: Missing or malformed X-Payment-Signature header");
+    }
+
+    #[test]
+    fn non_hex_signature_is_rejected() {
+        let req = TestRequest::default().insert_header((SIGNATURE_HEADER, "not hex")).to_http_request();
+
+        assert_eq!(provider().parse_webhook(&req, BODY).unwrap_err(), "Error: Missing or malformed X-Payment-Signature header");
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        let provider = provider();
+        let body = br#"{"reference": "mock_1", "status": "refunded"}"#;
+
+        assert_eq!(provider.parse_webhook(&signed(&provider, body), body).unwrap_err(), "Error: Unknown payment status refunded");
+    }
+
+    #[sqlx::test]
+    async fn start_payment_reuses_the_pending_payment(db_pool: PgPool) -> sqlx::Result<()> {
+        let user_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO \"user\"(user_id, full_name, email_address, address, password) VALUES($1, 'Buyer', 'buyer@example.com', 'Street', 'hash')")
+            .bind(user_id)
+            .execute(&db_pool).await?;
+
+        let provider = provider();
+        let target = PaymentTarget::Rent(Uuid::new_v4());
+        let mut conn = db_pool.acquire().await?;
+
+        let first = start_payment(&provider, &mut conn, user_id, target, 1_000).await.unwrap();
+        let second = start_payment(&provider, &mut conn, user_id, target, 1_000).await.unwrap();
+
+        assert_eq!(first.payment_id, second.payment_id);
+        assert_eq!(first.provider_reference, second.provider_reference);
+        assert_eq!(first.status, "Pending");
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment").fetch_one(&db_pool).await?;
+        assert_eq!(count, 1);
+
+        let installment = start_payment(&provider, &mut conn, user_id, PaymentTarget::SaleInstallment(Uuid::new_v4(), 1), 500).await.unwrap();
+        assert_ne!(installment.payment_id, first.payment_id);
+
+        Ok(())
+    }
+}
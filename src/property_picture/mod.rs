@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+
+use actix_multipart::{form::tempfile::TempFile, MultipartError};
+use actix_web::{delete, error::PayloadError, patch, web::{self, ServiceConfig}, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use image::ImageFormat;
+use processing::{process_picture, variant_urls, PictureVariants};
+use slug::slugify;
+use sqlx::{prelude::FromRow, types::Json, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{metrics::Metrics, owner::authorize_listing_owner, storage::Storage, utils::{auth::OwnerSession, error::{AppError, OrNotFound}, hash_file, models::{ApiResponse, Session}}, AppState};
+
+pub mod processing;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+pub enum PropertyKind {
+    #[serde(rename = "rent-property")]
+    Rent,
+    #[serde(rename = "sale-property")]
+    Sale,
+}
+
+impl PropertyKind {
+    /// Storage prefix pictures of this kind of property are kept under.
+    pub fn storage_dir(&self) -> &'static str {
+        match self {
+            PropertyKind::Rent => "rents",
+            PropertyKind::Sale => "sales",
+        }
+    }
+
+    /// Storage key of `file_name` for this kind of property.
+    pub fn storage_key(&self, file_name: &str) -> String {
+        format!("{}/{}", self.storage_dir(), file_name)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct PropertyPicture {
+    pub picture_id: Uuid,
+    #[serde(skip)]
+    pub property_id: Uuid,
+    pub original_name: String,
+    pub picture_url: String,
+    pub position: i32,
+    pub is_cover: bool,
+    pub variants: Json<PictureVariants>,
+}
+
+/// A property together with its ordered picture gallery.
+#[derive(Debug, Serialize)]
+pub struct WithPictures<T> {
+    #[serde(flatten)]
+    pub property: T,
+    pub pictures: Vec<PropertyPicture>,
+}
+
+/// A picture written to the upload directory but not yet recorded in the
+/// database.
+pub struct StoredPicture {
+    pub file_name: String,
+    pub original_name: String,
+    pub picture_url: String,
+    pub variants: PictureVariants,
+}
+
+#[derive(Debug, Deserialize)]
+struct PictureOrderForm {
+    picture_ids: Vec<Uuid>,
+}
+
+/// Slugged, human-readable version of an uploaded file name, kept for
+/// display only.
+fn display_name(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}.{}", slugify(stem), slugify(extension)),
+        None => slugify(name),
+    }
+}
+
+/// Reports a rejected multipart body in the usual envelope, naming the
+/// configured limit when the upload was too large.
+pub fn multipart_error(err: MultipartError, req: &HttpRequest) -> actix_web::Error {
+    match err {
+        MultipartError::Payload(PayloadError::Overflow) => {
+            let limit = req.app_data::<web::Data<AppState>>().map_or(0, |state| state.config.max_upload_bytes);
+            AppError::PayloadTooLarge(format!("Error: Upload exceeds {} bytes", limit)).into()
+        },
+        err => AppError::BadRequest(format!("Error: {}", err)).into(),
+    }
+}
+
+/// Checks every uploaded file is a JPEG or PNG, then saves them all to
+/// `storage`. Nothing is written unless every file is valid.
+///
+/// Files are stored under the SHA-256 of their content, so identical uploads
+/// share one file and different files can never overwrite each other. Each
+/// picture is re-encoded into thumbnail, medium and full variants, plus WebP
+/// copies of each, with all metadata stripped.
+///
+/// Stored files are never deleted here, not even when the request fails
+/// afterwards: a concurrent upload of the same content may already be
+/// relying on them. `reconcile-uploads` removes the ones left unreferenced.
+pub async fn store_uploaded_pictures(storage: &dyn Storage, metrics: &Metrics, kind: PropertyKind, pictures: &[TempFile]) -> Result<Vec<StoredPicture>, AppError> {
+    if pictures.is_empty() {
+        return Err(AppError::BadRequest("Error: At least one picture is required".to_string()));
+    }
+
+    let mut validated = Vec::with_capacity(pictures.len());
+
+    for picture in pictures {
+        let original_name = match picture.file_name.as_deref() {
+            Some(name) => display_name(name),
+            None => return Err(AppError::BadRequest("Error: Unable to get file name".to_string())),
+        };
+
+        // check extension of file
+        let source = picture.file.path().to_path_buf();
+        let file_type = web::block(move || infer::get_from_path(source)).await
+            .map_err(|err| AppError::Internal(format!("Failed reading uploaded file: {}", err)))?
+            .map_err(|err| AppError::Internal(format!("Failed reading uploaded file: {}", err)))?
+            .ok_or_else(|| AppError::BadRequest("Error: File type unknown".to_string()))?;
+
+        let (extension, format) = if file_type.mime_type() == "image/jpeg" && (file_type.extension() == "jpg" || file_type.extension() == "jpeg") {
+            (file_type.extension(), ImageFormat::Jpeg)
+        } else if file_type.mime_type() == "image/png" && file_type.extension() == "png" {
+            (file_type.extension(), ImageFormat::Png)
+        } else {
+            return Err(AppError::BadRequest("Invalid file type. Picture must be in [.jpg, .jpeg, .png]".to_string()));
+        };
+
+        let source = picture.file.path().to_path_buf();
+        let content_hash = web::block(move || hash_file(&source)).await
+            .map_err(|err| AppError::Internal(format!("Failed reading uploaded file: {}", err)))?
+            .map_err(|err| AppError::Internal(format!("Failed reading uploaded file: {}", err)))?;
+
+        validated.push((format!("{}.{}", content_hash, extension), original_name, format));
+    }
+
+    let mut stored = Vec::with_capacity(pictures.len());
+
+    for (picture, (file_name, original_name, format)) in pictures.iter().zip(validated) {
+        store_picture(storage, kind, picture, &file_name, format).await?;
+
+        stored.push(StoredPicture {
+            picture_url: storage.public_url(&kind.storage_key(&file_name)),
+            variants: variant_urls(&file_name, |name| storage.public_url(&kind.storage_key(name))),
+            file_name,
+            original_name,
+        });
+    }
+
+    metrics.record_upload(kind, pictures.len(), pictures.iter().map(|picture| picture.size).sum());
+
+    Ok(stored)
+}
+
+/// Writes every variant of one picture unless identical content is already
+/// stored under `file_name`.
+async fn store_picture(storage: &dyn Storage, kind: PropertyKind, picture: &TempFile, file_name: &str, format: ImageFormat) -> Result<(), AppError> {
+    if storage.exists(&kind.storage_key(file_name)).await.map_err(AppError::Internal)? {
+        return Ok(());
+    }
+
+    let source = picture.file.path().to_path_buf();
+    let processed_name = file_name.to_string();
+
+    let processed = web::block(move || process_picture(&source, &processed_name, format)).await
+        .map_err(|err| AppError::Internal(format!("Unable to process picture: {}", err)))?
+        .map_err(AppError::BadRequest)?;
+
+    // The full variant is written last: its presence marks the picture as
+    // completely stored.
+    for file in processed.into_iter().rev() {
+        storage.put(&kind.storage_key(&file.file_name), file.contents).await.map_err(AppError::Internal)?;
+    }
+
+    Ok(())
+}
+
+/// Records `pictures` for a property in upload order, the first one as cover.
+pub async fn insert_pictures(conn: &mut PgConnection, kind: PropertyKind, property_id: Uuid, pictures: &[StoredPicture]) -> sqlx::Result<Vec<PropertyPicture>> {
+    let (rent_property_id, sale_property_id) = match kind {
+        PropertyKind::Rent => (Some(property_id), None),
+        PropertyKind::Sale => (None, Some(property_id)),
+    };
+
+    let mut inserted = Vec::with_capacity(pictures.len());
+
+    for (position, picture) in pictures.iter().enumerate() {
+        let row = sqlx::query_as!(
+            PropertyPicture,
+            "INSERT INTO property_picture(picture_id, rent_property_id, sale_property_id, file_name, original_name, picture_url, position, is_cover, variants)
+                VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                RETURNING picture_id, COALESCE(rent_property_id, sale_property_id) AS \"property_id!\", original_name, picture_url,
+                    position, is_cover, variants AS \"variants: Json<PictureVariants>\"",
+            Uuid::new_v4(),
+            rent_property_id,
+            sale_property_id,
+            picture.file_name,
+            picture.original_name,
+            picture.picture_url,
+            position as i32,
+            position == 0,
+            Json(&picture.variants) as _
+        ).fetch_one(&mut *conn).await?;
+
+        inserted.push(row);
+    }
+
+    Ok(inserted)
+}
+
+/// Replaces a property's whole gallery with `pictures`.
+pub async fn replace_pictures(conn: &mut PgConnection, kind: PropertyKind, property_id: Uuid, pictures: &[StoredPicture]) -> sqlx::Result<Vec<PropertyPicture>> {
+    sqlx::query!(
+        "DELETE FROM property_picture WHERE rent_property_id = $1 OR sale_property_id = $1",
+        property_id
+    ).execute(&mut *conn).await?;
+
+    let inserted = insert_pictures(conn, kind, property_id, pictures).await?;
+    sync_cover_url(conn, kind, property_id).await?;
+
+    Ok(inserted)
+}
+
+/// Fetches the galleries of every property in `property_ids`, keyed by
+/// property and ordered by position.
+pub async fn get_pictures(db_pool: &PgPool, property_ids: &[Uuid]) -> sqlx::Result<HashMap<Uuid, Vec<PropertyPicture>>> {
+    let rows = sqlx::query_as!(
+        PropertyPicture,
+        "SELECT picture_id, COALESCE(rent_property_id, sale_property_id) AS \"property_id!\", original_name, picture_url,
+                position, is_cover, variants AS \"variants: Json<PictureVariants>\"
+            FROM property_picture
+            WHERE rent_property_id = ANY($1) OR sale_property_id = ANY($1)
+            ORDER BY position",
+        property_ids
+    ).fetch_all(db_pool).await?;
+
+    let mut pictures: HashMap<Uuid, Vec<PropertyPicture>> = HashMap::new();
+    for row in rows {
+        pictures.entry(row.property_id).or_default().push(row);
+    }
+
+    Ok(pictures)
+}
+
+/// Pairs each property with its gallery.
+pub async fn attach_pictures<T>(db_pool: &PgPool, properties: Vec<T>, property_id: impl Fn(&T) -> Uuid) -> sqlx::Result<Vec<WithPictures<T>>> {
+    let property_ids: Vec<Uuid> = properties.iter().map(&property_id).collect();
+    let mut pictures = get_pictures(db_pool, &property_ids).await?;
+
+    Ok(properties.into_iter().map(|property| {
+        let pictures = pictures.remove(&property_id(&property)).unwrap_or_default();
+        WithPictures { property, pictures }
+    }).collect())
+}
+
+async fn get_property_owner_id(db_pool: &PgPool, kind: PropertyKind, property_id: Uuid) -> sqlx::Result<Uuid> {
+    match kind {
+        PropertyKind::Rent => sqlx::query_scalar!(
+            "SELECT owner_id FROM rent_property WHERE rent_property_id = $1 AND status != 'Deleted'",
+            property_id
+        ).fetch_one(db_pool).await,
+        PropertyKind::Sale => sqlx::query_scalar!(
+            "SELECT owner_id FROM sale_property WHERE sale_property_id = $1 AND status != 'Deleted'",
+            property_id
+        ).fetch_one(db_pool).await,
+    }
+}
+
+/// Keeps the property's own `picture_url` pointing at its cover picture.
+async fn sync_cover_url(conn: &mut PgConnection, kind: PropertyKind, property_id: Uuid) -> sqlx::Result<()> {
+    match kind {
+        PropertyKind::Rent => sqlx::query!(
+            "UPDATE rent_property SET picture_url = pp.picture_url
+                FROM property_picture pp
+                WHERE rent_property.rent_property_id = $1 AND pp.rent_property_id = $1 AND pp.is_cover",
+            property_id
+        ).execute(&mut *conn).await?,
+        PropertyKind::Sale => sqlx::query!(
+            "UPDATE sale_property SET picture_url = pp.picture_url
+                FROM property_picture pp
+                WHERE sale_property.sale_property_id = $1 AND pp.sale_property_id = $1 AND pp.is_cover",
+            property_id
+        ).execute(&mut *conn).await?,
+    };
+
+    Ok(())
+}
+
+/// Looks up a property that has not been deleted and checks the caller may
+/// edit it.
+pub async fn authorize_property_edit(db_pool: &PgPool, session: &Session, kind: PropertyKind, property_id: Uuid) -> Result<(), AppError> {
+    let owner_id = get_property_owner_id(db_pool, kind, property_id).await
+        .or_not_found(format!("Error: No property matching id: {}", property_id))?;
+
+    authorize_listing_owner(db_pool, session, owner_id).await
+}
+
+async fn respond_with_gallery(db_pool: &PgPool, property_id: Uuid, message: &str) -> Result<HttpResponse, AppError> {
+    let mut pictures = get_pictures(db_pool, &[property_id]).await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, message.to_string(), Some(pictures.remove(&property_id).unwrap_or_default()), None)
+    ))
+}
+
+#[patch("/api/{kind:(rent|sale)-property}/{property_id}/pictures/order")]
+async fn reorder_pictures(app_state: web::Data<AppState>, session: OwnerSession, path: web::Path<(PropertyKind, Uuid)>, order_form: web::Json<PictureOrderForm>) -> Result<HttpResponse, AppError> {
+    let (kind, property_id) = path.into_inner();
+
+    authorize_property_edit(&app_state.db_pool, &session, kind, property_id).await?;
+
+    let mut current: Vec<Uuid> = get_pictures(&app_state.db_pool, &[property_id]).await?
+        .remove(&property_id).unwrap_or_default().into_iter().map(|picture| picture.picture_id).collect();
+
+    let mut requested = order_form.picture_ids.clone();
+    current.sort();
+    requested.sort();
+
+    if current != requested {
+        return Err(AppError::BadRequest("picture_ids must list every picture of the property exactly once".to_string()));
+    }
+
+    let mut trx = app_state.db_pool.begin().await?;
+
+    for (position, picture_id) in order_form.picture_ids.iter().enumerate() {
+        sqlx::query!(
+            "UPDATE property_picture SET position = $2 WHERE picture_id = $1",
+            picture_id,
+            position as i32
+        ).execute(&mut *trx).await?;
+    }
+
+    trx.commit().await?;
+
+    respond_with_gallery(&app_state.db_pool, property_id, "Successfully reordered pictures").await
+}
+
+#[patch("/api/{kind:(rent|sale)-property}/{property_id}/pictures/{picture_id}/cover")]
+async fn set_cover_picture(app_state: web::Data<AppState>, session: OwnerSession, path: web::Path<(PropertyKind, Uuid, Uuid)>) -> Result<HttpResponse, AppError> {
+    let (kind, property_id, picture_id) = path.into_inner();
+
+    authorize_property_edit(&app_state.db_pool, &session, kind, property_id).await?;
+
+    let mut trx = app_state.db_pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE property_picture SET is_cover = FALSE
+            WHERE (rent_property_id = $1 OR sale_property_id = $1) AND is_cover",
+        property_id
+    ).execute(&mut *trx).await?;
+
+    let done = sqlx::query!(
+        "UPDATE property_picture SET is_cover = TRUE
+            WHERE picture_id = $2 AND (rent_property_id = $1 OR sale_property_id = $1)",
+        property_id,
+        picture_id
+    ).execute(&mut *trx).await?;
+
+    if done.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!("Error: No picture matching id: {}", picture_id)));
+    }
+
+    sync_cover_url(&mut trx, kind, property_id).await?;
+
+    trx.commit().await?;
+
+    respond_with_gallery(&app_state.db_pool, property_id, "Successfully set cover picture").await
+}
+
+#[delete("/api/{kind:(rent|sale)-property}/{property_id}/pictures/{picture_id}")]
+async fn delete_picture(app_state: web::Data<AppState>, session: OwnerSession, path: web::Path<(PropertyKind, Uuid, Uuid)>) -> Result<HttpResponse, AppError> {
+    let (kind, property_id, picture_id) = path.into_inner();
+
+    authorize_property_edit(&app_state.db_pool, &session, kind, property_id).await?;
+
+    let pictures = get_pictures(&app_state.db_pool, &[property_id]).await?.remove(&property_id).unwrap_or_default();
+
+    let picture = pictures.iter().find(|picture| picture.picture_id == picture_id).cloned()
+        .ok_or_else(|| AppError::NotFound(format!("Error: No picture matching id: {}", picture_id)))?;
+
+    if pictures.len() == 1 {
+        return Err(AppError::BadRequest("Error: A property must keep at least one picture".to_string()));
+    }
+
+    let mut trx = app_state.db_pool.begin().await?;
+
+    sqlx::query!(
+        "DELETE FROM property_picture WHERE picture_id = $1",
+        picture_id
+    ).execute(&mut *trx).await?;
+
+    // Close the gap left in the ordering and hand the cover to the next
+    // picture when the cover itself was deleted.
+    let remaining: Vec<&PropertyPicture> = pictures.iter().filter(|other| other.picture_id != picture_id).collect();
+    for (position, other) in remaining.iter().enumerate() {
+        let is_cover = other.is_cover || (picture.is_cover && position == 0);
+
+        sqlx::query!(
+            "UPDATE property_picture SET position = $2, is_cover = $3 WHERE picture_id = $1",
+            other.picture_id,
+            position as i32,
+            is_cover
+        ).execute(&mut *trx).await?;
+    }
+
+    sync_cover_url(&mut trx, kind, property_id).await?;
+
+    trx.commit().await?;
+
+    respond_with_gallery(&app_state.db_pool, property_id, "Successfully deleted picture").await
+}
+
+pub fn init_routes(cfg: &mut ServiceConfig) {
+    cfg
+        .service(reorder_pictures)
+        .service(set_cover_picture)
+        .service(delete_picture);
+}
@@ -0,0 +1,168 @@
+use std::{io::Cursor, path::Path};
+
+use image::{codecs::jpeg::JpegEncoder, error::ImageError, imageops::FilterType, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use serde::{Deserialize, Serialize};
+
+/// Longest edge accepted for an uploaded picture, in pixels.
+const MAX_SOURCE_DIMENSION: u32 = 8192;
+
+/// Largest picture accepted, in pixels. Decoding allocates a few bytes per
+/// pixel, so this bounds the memory one upload can take.
+const MAX_SOURCE_PIXELS: u64 = 40_000_000;
+
+/// The sizes every uploaded picture is published in.
+#[derive(Debug, Clone, Copy)]
+pub enum Variant {
+    Thumbnail,
+    Medium,
+    Full,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 3] = [Variant::Thumbnail, Variant::Medium, Variant::Full];
+
+    /// Longest edge in pixels. Larger pictures are scaled down to fit.
+    fn max_dimension(&self) -> u32 {
+        match self {
+            Variant::Thumbnail => 320,
+            Variant::Medium => 1024,
+            Variant::Full => 2048,
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            Variant::Thumbnail => "-thumb",
+            Variant::Medium => "-medium",
+            Variant::Full => "",
+        }
+    }
+}
+
+/// Public URLs of one variant.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VariantUrls {
+    pub url: String,
+    pub webp_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PictureVariants {
+    pub thumbnail: VariantUrls,
+    pub medium: VariantUrls,
+    pub full: VariantUrls,
+}
+
+/// An encoded file ready to be written to the upload directory.
+pub struct ProcessedFile {
+    pub file_name: String,
+    pub contents: Vec<u8>,
+}
+
+/// File name of `variant` for the picture stored as `file_name`
+/// (`<hash>.<ext>`), either in the original format or as WebP.
+pub fn variant_file_name(file_name: &str, variant: Variant, webp: bool) -> String {
+    let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+    let extension = if webp { "webp" } else { extension };
+
+    format!("{}{}.{}", stem, variant.suffix(), extension)
+}
+
+/// Every file written for the picture stored as `file_name`.
+pub fn all_variant_file_names(file_name: &str) -> Vec<String> {
+    Variant::ALL.iter()
+        .flat_map(|variant| [variant_file_name(file_name, *variant, false), variant_file_name(file_name, *variant, true)])
+        .collect()
+}
+
+/// Public URLs of every variant, given how a stored file name maps to a URL.
+pub fn variant_urls(file_name: &str, url_of: impl Fn(&str) -> String) -> PictureVariants {
+    let urls = |variant| VariantUrls {
+        url: url_of(&variant_file_name(file_name, variant, false)),
+        webp_url: Some(url_of(&variant_file_name(file_name, variant, true))),
+    };
+
+    PictureVariants { thumbnail: urls(Variant::Thumbnail), medium: urls(Variant::Medium), full: urls(Variant::Full) }
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let mut contents = Vec::new();
+
+    let result = match format {
+        ImageFormat::Jpeg => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut contents, 85)),
+        ImageFormat::WebP => return encode_webp(image),
+        _ => image.write_to(&mut Cursor::new(&mut contents), format),
+    };
+
+    result.map_err(|err| err.to_string())?;
+
+    Ok(contents)
+}
+
+/// Lossy WebP. The image crate only writes lossless WebP, which comes out
+/// several times larger than the JPEG it is meant to replace.
+fn encode_webp(image: &DynamicImage) -> Result<Vec<u8>, String> {
+    let encoded = if image.color().has_alpha() {
+        let rgba = image.to_rgba8();
+        webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode_simple(false, 80.0)
+    } else {
+        let rgb = image.to_rgb8();
+        webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode_simple(false, 80.0)
+    };
+
+    encoded.map(|webp| webp.to_vec()).map_err(|err| format!("Unable to encode WebP: {:?}", err))
+}
+
+fn too_large() -> String {
+    format!("Error: Pictures must be at most {0}x{0} pixels and {1} megapixels", MAX_SOURCE_DIMENSION, MAX_SOURCE_PIXELS / 1_000_000)
+}
+
+/// Decodes the picture at `source`, applies its EXIF orientation and
+/// re-encodes every variant. Re-encoding drops all metadata, including GPS
+/// coordinates recorded by phones. Pictures over `MAX_SOURCE_DIMENSION` or
+/// `MAX_SOURCE_PIXELS` are refused before they are decoded.
+///
+/// This is CPU heavy; call it from a blocking task.
+pub fn process_picture(source: &Path, file_name: &str, format: ImageFormat) -> Result<Vec<ProcessedFile>, String> {
+    let mut reader = ImageReader::open(source).map_err(|err| err.to_string())?
+        .with_guessed_format().map_err(|err| err.to_string())?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(|err| match err {
+        ImageError::Limits(_) => too_large(),
+        err => err.to_string(),
+    })?;
+
+    let (width, height) = decoder.dimensions();
+    if u64::from(width) * u64::from(height) > MAX_SOURCE_PIXELS {
+        return Err(too_large());
+    }
+
+    let orientation = decoder.orientation().map_err(|err| err.to_string())?;
+
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|err| match err {
+        ImageError::Limits(_) => too_large(),
+        err => err.to_string(),
+    })?;
+    image.apply_orientation(orientation);
+
+    let mut files = Vec::with_capacity(Variant::ALL.len() * 2);
+
+    for variant in Variant::ALL {
+        let max_dimension = variant.max_dimension();
+        let resized = if image.width() > max_dimension || image.height() > max_dimension {
+            image.resize(max_dimension, max_dimension, FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+
+        files.push(ProcessedFile { file_name: variant_file_name(file_name, variant, false), contents: encode(&resized, format)? });
+        files.push(ProcessedFile { file_name: variant_file_name(file_name, variant, true), contents: encode(&resized, ImageFormat::WebP)? });
+    }
+
+    Ok(files)
+}
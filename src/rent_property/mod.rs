@@ -1,23 +1,21 @@
-use std::env;
-
 use actix_multipart::form::{tempfile::TempFile, text::Text, MultipartForm};
-use actix_web::{get, post, web::{self, ServiceConfig}, HttpRequest, HttpResponse, Responder};
-use chrono::{NaiveDate};
+use actix_web::{delete, get, patch, post, web::{self, ServiceConfig}, HttpResponse};
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use pricing::{quote_rent, RentQuote};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use slug::slugify;
-use sqlx::{prelude::FromRow};
+use sqlx::{prelude::FromRow, PgConnection};
 use uuid::Uuid;
 
-use crate::{utils::models::ApiResponse, AppState};
+use crate::{listing::ListingFilter, owner::resolve_listing_owner, pagination::{Paginated, Pagination, SortColumn, SortKey, SortValue}, payment::{start_payment, PaymentTarget}, property_picture::{attach_pictures, authorize_property_edit, insert_pictures, replace_pictures, store_uploaded_pictures, PropertyKind, WithPictures}, transaction::{check_payment_deadline, TransactionKind, TransactionStatus}, utils::{auth::{AuthSession, OwnerSession}, error::{AppError, OrNotFound, EXCLUSION_VIOLATION}, models::ApiResponse}, AppState};
 
-use super::utils::save_uploaded_file;
+pub mod pricing;
 
 #[derive(Debug, MultipartForm)]
 struct RentUploadForm {
     #[multipart(rename = "picture")]
-    picture: TempFile,
-    owner: Text<String>,
+    picture: Vec<TempFile>,
+    owner: Option<Text<String>>,
     title: Text<String>,
     description: Text<String>,
     address: Text<String>,
@@ -25,7 +23,26 @@ struct RentUploadForm {
     lb: Text<i32>,
     bedroom: Text<i16>,
     bathroom: Text<i16>,
-    monthly_rent: Text<i64>
+    monthly_rent: Text<i64>,
+    min_rent_days: Option<Text<i32>>
+}
+
+/// Every field is optional; only the ones sent are changed. Sending any
+/// picture replaces the whole gallery.
+#[derive(Debug, MultipartForm)]
+struct RentUpdateForm {
+    #[multipart(rename = "picture")]
+    picture: Vec<TempFile>,
+    title: Option<Text<String>>,
+    description: Option<Text<String>>,
+    address: Option<Text<String>>,
+    lt: Option<Text<i32>>,
+    lb: Option<Text<i32>>,
+    bedroom: Option<Text<i16>>,
+    bathroom: Option<Text<i16>>,
+    monthly_rent: Option<Text<i64>>,
+    min_rent_days: Option<Text<i32>>,
+    status: Option<Text<String>>
 }
 
 #[derive(Debug, Deserialize, Serialize, FromRow)]
@@ -34,70 +51,58 @@ pub struct RentProperty {
     pub title: String,
     pub description: String,
     pub address: String,
-    pub owner_id: String,
+    pub owner_id: Uuid,
     pub lt: i32,
     pub lb: i32,
     pub bedroom: i16,
     pub bathroom: i16,
     pub monthly_rent: i64,
+    pub min_rent_days: i32,
     pub picture_url: String,
-    pub status: String
+    pub status: String,
+    pub created_at: DateTime<Utc>,
 }
 
-#[post("/api/rent-property")]
-async fn add_rent_property(app_state: web::Data<AppState>, mp: MultipartForm<RentUploadForm>) -> impl Responder {
-    let host_address = env::var("HOST_URL").expect("Please provide HOST URL");
-    let picture_name = match mp.picture.file_name.clone() {
-        Some(name) => {
-            let file_path : Vec<&str> = name.split(".").collect();
-            format!("{}.{}", slugify(file_path[0]), file_path[file_path.len() - 1])
-        },
-        None => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Uploaded file error".to_string(), None, Some("Error: Unable to get file name".to_string()))
-        )
-    };
-    let file_path = format!("./uploaded/rents/{}", picture_name);
-    let url_path = format!("{}/rent-pictures/{}", host_address, picture_name);
-
-    // check extension of file
-    match infer::get_from_path(mp.picture.file.path()) {
-        Err(err) => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed reading uploaded file".to_string(), None, Some(err.to_string()))
-        ),
-        Ok(path) => match path {
-            None => return HttpResponse::BadRequest().json(
-                ApiResponse::<()>::new(false, "Unable to read file type".to_string(), None, Some("Error: File type unknown".to_string()))
-            ),
-            Some(kind) => {
-                if (kind.mime_type() == "image/jpeg" && (kind.extension() == "jpg" || kind.extension() == "jpeg")) || (kind.mime_type() == "image/png" && kind.extension() == "png") {
-                    println!("Image extension valid");
-                } else {
-                    return HttpResponse::BadRequest().json(
-                        ApiResponse::<()>::new(false, "Invalid file extension".to_string(), None, Some("Invalid file type. Picture must be in [.jpg, .jpeg, .png]".to_string()))
-                    );
-                }
-            }
-        }
+impl Paginated for RentProperty {
+    const ID_COLUMN: &'static str = "rent_property_id";
+
+    fn sort_column(key: SortKey) -> Option<SortColumn> {
+        Some(match key {
+            SortKey::Newest => SortColumn::Time("created_at"),
+            SortKey::Price => SortColumn::Number("monthly_rent"),
+            SortKey::Size => SortColumn::Number("lb"),
+        })
     }
 
-    if let Err(err) = save_uploaded_file(&mp.picture, &file_path).await {
-        return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed saving file".to_string(), None, Some(err.to_string()))
-        );
+    fn position(&self, key: SortKey) -> (SortValue, Uuid) {
+        let value = match key {
+            SortKey::Newest => SortValue::Time(self.created_at),
+            SortKey::Price => SortValue::Number(self.monthly_rent),
+            SortKey::Size => SortValue::Number(self.lb.into()),
+        };
+
+        (value, self.rent_property_id)
+    }
+}
+
+#[post("/api/rent-property")]
+async fn add_rent_property(app_state: web::Data<AppState>, session: OwnerSession, mp: MultipartForm<RentUploadForm>) -> Result<HttpResponse, AppError> {
+    let owner_id = resolve_listing_owner(&app_state.db_pool, &session, mp.owner.as_deref().map(String::as_str)).await?;
+
+    if mp.min_rent_days.as_deref().is_some_and(|days| *days < 1) {
+        return Err(AppError::BadRequest("Error: min_rent_days must be at least 1".to_string()));
     }
 
+    let pictures = store_uploaded_pictures(app_state.storage.as_ref(), &app_state.metrics, PropertyKind::Rent, &mp.picture).await?;
+
+    let mut trx = app_state.db_pool.begin().await?;
+
     let property_id = Uuid::new_v4();
-    let owner_id = match Uuid::parse_str(&mp.owner) {
-        Ok(uuid) => uuid,
-        Err(_) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Failed converting owner ID".to_string(), None, Some("Error: Invalid UUID format on owner_id".to_string()))
-        )
-    };
 
-    let result = sqlx::query_as!(
+    let property = sqlx::query_as!(
         RentProperty,
-        "INSERT INTO rent_property(rent_property_id, title, description, address, owner_id, lt, lb, bedroom, bathroom, monthly_rent, picture_url, status)
-            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, 'Available')
+        "INSERT INTO rent_property(rent_property_id, title, description, address, owner_id, lt, lb, bedroom, bathroom, monthly_rent, min_rent_days, picture_url, status)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, 'Available')
             RETURNING *",
             property_id,
             *mp.title,
@@ -109,68 +114,236 @@ async fn add_rent_property(app_state: web::Data<AppState>, mp: MultipartForm<Ren
             *mp.bedroom,
             *mp.bathroom,
             *mp.monthly_rent,
-            url_path
-    ).fetch_one(&app_state.db_pool).await;
+            mp.min_rent_days.as_deref().copied().unwrap_or(1),
+            pictures[0].picture_url
+    ).fetch_one(&mut *trx).await?;
 
+    let pictures = insert_pictures(&mut trx, PropertyKind::Rent, property_id, &pictures).await?;
 
-    match result {
-        Err(err) => HttpResponse::InternalServerError().json(ApiResponse::<()>::new(false, "Failed inserting new rent_property".to_string(), None, Some(err.to_string()))),
-        Ok(property) => HttpResponse::Ok().json(ApiResponse::new(true, "Successfully insert new property".to_string(), Some(property), None))
-    }
+    trx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(true, "Successfully insert new property".to_string(), Some(WithPictures { property, pictures }), None)))
 }
 
 
 
 #[get("/api/rent-property")]
-async fn get_rent_properties(app_state: web::Data<AppState>) -> impl Responder {
-    let result = sqlx::query_as!(
-        RentProperty,
-        "SELECT * FROM rent_property WHERE rent_property_id not in 
-        (
-        SELECT rent_property_id FROM rent_transaction
-        WHERE status != 'Cancelled' AND end_date > CURRENT_DATE
-        )"
-    ).fetch_all(&app_state.db_pool).await;
-
-    match result {
-     Ok(properties) => HttpResponse::Ok().json(
-        ApiResponse::new(true, "Successfully retrieved rental properties".to_string(), Some(properties), None)
-     ),
-     Err(_) => HttpResponse::InternalServerError().json(
-        ApiResponse::<()>::new(false, "Failed retrieving rental properties".to_string(), None, Some("Server unable to retrieve data".to_string()))
-     )
-    }
+async fn get_rent_properties(app_state: web::Data<AppState>, session: Option<AuthSession>, filter: web::Query<ListingFilter>, page: Pagination<RentProperty>) -> Result<HttpResponse, AppError> {
+    let mut filter = filter.into_inner();
+
+    filter.authorize(&app_state.db_pool, session.as_deref()).await?;
+
+    let mut properties = page.fetch(&app_state.db_pool, |query| {
+        query.push(
+            "SELECT * FROM rent_property WHERE rent_property_id not in 
+            (
+            SELECT rent_property_id FROM rent_transaction
+            WHERE status != 'Cancelled' AND daterange(start_date, end_date) @> CURRENT_DATE
+            )"
+        );
+        filter.push_conditions(query, "monthly_rent");
+    }).await?;
+
+    let items = attach_pictures(&app_state.db_pool, std::mem::take(&mut properties.items), |property| property.rent_property_id).await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully retrieved rental properties".to_string(), Some(properties.with_items(items)), None)
+    ))
 }
 
 #[get("/api/rent-property/{rent_property_id}")]
-async fn get_rent_property_by_id(app_state: web::Data<AppState>, rent_property_id: web::Path<Uuid>) -> impl Responder {
-    let result = sqlx::query_as!(
+async fn get_rent_property_by_id(app_state: web::Data<AppState>, rent_property_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let property = sqlx::query_as!(
         RentProperty,
-        "SELECT * FROM rent_property WHERE rent_property_id = $1 
-        AND rent_property_id not in 
-        (
-        SELECT rent_property_id FROM rent_transaction
-        WHERE status != 'Cancelled' AND end_date > CURRENT_DATE
-        )",
+        "SELECT * FROM rent_property WHERE rent_property_id = $1 AND status = 'Available'",
         *rent_property_id
-    ).fetch_one(&app_state.db_pool).await;
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No property matching id: {}", *rent_property_id))?;
+
+    let property = attach_pictures(&app_state.db_pool, vec![property], |property| property.rent_property_id).await?.remove(0);
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(true,"Successfully retrieved property".to_string(), Some(property), None)))
+}
 
-    match result {
-        Ok(property) => HttpResponse::Ok().json(ApiResponse::new(true,"Successfully retrieved property".to_string(), Some(property), None)),
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Property not found".to_string(), None, Some(format!("Error: No property matching id: {}", *rent_property_id)))
-            ),
-            _ => HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Unable to retrieve property".to_string(), None, Some(err.to_string()))
-            )
+#[patch("/api/rent-property/{rent_property_id}")]
+async fn update_rent_property(app_state: web::Data<AppState>, session: OwnerSession, rent_property_id: web::Path<Uuid>, mp: MultipartForm<RentUpdateForm>) -> Result<HttpResponse, AppError> {
+    let property_id = *rent_property_id;
+
+    authorize_property_edit(&app_state.db_pool, &session, PropertyKind::Rent, property_id).await?;
+
+    // Deleting goes through DELETE, so listings can only be hidden or shown here.
+    if let Some(status) = &mp.status {
+        if !["Available", "Unlisted"].contains(&status.as_str()) {
+            return Err(AppError::BadRequest("Error: status must be Available or Unlisted".to_string()));
         }
     }
+
+    if mp.min_rent_days.as_deref().is_some_and(|days| *days < 1) {
+        return Err(AppError::BadRequest("Error: min_rent_days must be at least 1".to_string()));
+    }
+
+    let pictures = if mp.picture.is_empty() {
+        None
+    } else {
+        Some(store_uploaded_pictures(app_state.storage.as_ref(), &app_state.metrics, PropertyKind::Rent, &mp.picture).await?)
+    };
+
+    let mut trx = app_state.db_pool.begin().await?;
+
+    if let Some(pictures) = &pictures {
+        replace_pictures(&mut trx, PropertyKind::Rent, property_id, pictures).await?;
+    }
+
+    let property = sqlx::query_as!(
+        RentProperty,
+        "UPDATE rent_property SET
+            title = COALESCE($2, title),
+            description = COALESCE($3, description),
+            address = COALESCE($4, address),
+            lt = COALESCE($5, lt),
+            lb = COALESCE($6, lb),
+            bedroom = COALESCE($7, bedroom),
+            bathroom = COALESCE($8, bathroom),
+            monthly_rent = COALESCE($9, monthly_rent),
+            min_rent_days = COALESCE($10, min_rent_days),
+            status = COALESCE($11, status)
+        WHERE rent_property_id = $1 AND status != 'Deleted'
+        RETURNING *",
+        property_id,
+        mp.title.as_deref(),
+        mp.description.as_deref(),
+        mp.address.as_deref(),
+        mp.lt.as_deref(),
+        mp.lb.as_deref(),
+        mp.bedroom.as_deref(),
+        mp.bathroom.as_deref(),
+        mp.monthly_rent.as_deref(),
+        mp.min_rent_days.as_deref(),
+        mp.status.as_deref()
+    ).fetch_one(&mut *trx).await?;
+
+    trx.commit().await?;
+
+    let property = attach_pictures(&app_state.db_pool, vec![property], |property| property.rent_property_id).await?.remove(0);
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully updated property".to_string(), Some(property), None)
+    ))
+}
+
+/// Soft-deletes a listing. The row and its pictures stay so past
+/// transactions still resolve, but it is hidden everywhere.
+#[delete("/api/rent-property/{rent_property_id}")]
+async fn delete_rent_property(app_state: web::Data<AppState>, session: OwnerSession, rent_property_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let property_id = *rent_property_id;
+
+    authorize_property_edit(&app_state.db_pool, &session, PropertyKind::Rent, property_id).await?;
+
+    let is_rented = sqlx::query_scalar!(
+        "SELECT EXISTS(
+            SELECT 1 FROM rent_transaction
+            WHERE rent_property_id = $1 AND status != 'Cancelled' AND (end_date > CURRENT_DATE OR end_date IS NULL)
+        ) AS \"exists!\"",
+        property_id
+    ).fetch_one(&app_state.db_pool).await?;
+
+    if is_rented {
+        return Err(AppError::Conflict("Error: Property has an ongoing rent transaction".to_string()));
+    }
+
+    let property = sqlx::query_as!(
+        RentProperty,
+        "UPDATE rent_property SET status = 'Deleted' WHERE rent_property_id = $1 AND status != 'Deleted' RETURNING *",
+        property_id
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No property matching id: {}", property_id))?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully deleted property".to_string(), Some(property), None)
+    ))
 }
 
+/// Days from `start_date` up to, but not including, `end_date`.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+struct DateRange {
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+}
 
+#[derive(Debug, Serialize)]
+struct Availability {
+    rent_property_id: Uuid,
+    from: NaiveDate,
+    to: NaiveDate,
+    booked: Vec<DateRange>,
+    free: Vec<DateRange>,
+}
 
-use crate::{utils::{get_session}};
+#[derive(Debug, Deserialize)]
+struct AvailabilityQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// Splits `from..to` into the gaps left between `booked` ranges, which must
+/// be sorted and lie within the window.
+fn free_ranges(from: NaiveDate, to: NaiveDate, booked: &[DateRange]) -> Vec<DateRange> {
+    let mut free = Vec::new();
+    let mut cursor = from;
+
+    for range in booked {
+        if range.start_date > cursor {
+            free.push(DateRange { start_date: cursor, end_date: range.start_date });
+        }
+        cursor = cursor.max(range.end_date);
+    }
+
+    if cursor < to {
+        free.push(DateRange { start_date: cursor, end_date: to });
+    }
+
+    free
+}
+
+/// Booked and free periods of a property between `from` (default today) and
+/// `to` (default a year later).
+#[get("/api/rent-property/{rent_property_id}/availability")]
+async fn get_rent_availability(app_state: web::Data<AppState>, rent_property_id: web::Path<Uuid>, query: web::Query<AvailabilityQuery>) -> Result<HttpResponse, AppError> {
+    let from = query.from.unwrap_or(Local::now().date_naive());
+    let to = query.to.unwrap_or(from + Duration::days(365));
+
+    if to <= from {
+        return Err(AppError::BadRequest("Error: to must be after from".to_string()));
+    }
+
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM rent_property WHERE rent_property_id = $1 AND status = 'Available') AS \"exists!\"",
+        *rent_property_id
+    ).fetch_one(&app_state.db_pool).await?;
+
+    if !exists {
+        return Err(AppError::NotFound(format!("Error: No property matching id: {}", *rent_property_id)));
+    }
+
+    // Bookings are clipped to the window; open-ended ones run to its end.
+    let booked = sqlx::query_as!(
+        DateRange,
+        "SELECT GREATEST(start_date, $2) AS \"start_date!\", LEAST(COALESCE(end_date, $3), $3) AS \"end_date!\"
+            FROM rent_transaction
+            WHERE rent_property_id = $1 AND status != 'Cancelled'
+            AND daterange(start_date, end_date) && daterange($2, $3)
+            ORDER BY start_date",
+        *rent_property_id,
+        from,
+        to
+    ).fetch_all(&app_state.db_pool).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(true, "Successfully retrieved availability".to_string(), Some(Availability {
+        rent_property_id: *rent_property_id,
+        from,
+        to,
+        free: free_ranges(from, to, &booked),
+        booked,
+    }), None)))
+}
 
 #[derive(Debug, Deserialize, Serialize, FromRow)]
 struct RentTransaction {
@@ -180,7 +353,11 @@ struct RentTransaction {
     total_payment: Option<i64>,
     start_date: NaiveDate,
     end_date: Option<NaiveDate>,
-    status: String,
+    status: TransactionStatus,
+    /// When an unpaid transaction is cancelled if still unpaid.
+    payment_deadline: Option<DateTime<Utc>>,
+    cancelled_at: Option<DateTime<Utc>>,
+    refund_amount: Option<i64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, FromRow)]
@@ -190,10 +367,26 @@ struct RentTransactionObject {
     total_payment: Option<i64>,
     start_date: NaiveDate,
     end_date: Option<NaiveDate>,
-    status: String,
+    status: TransactionStatus,
+    payment_deadline: Option<DateTime<Utc>>,
     rent_property: Value
 }
 
+impl Paginated for RentTransactionObject {
+    const ID_COLUMN: &'static str = "rent_transaction_id";
+
+    fn sort_column(key: SortKey) -> Option<SortColumn> {
+        match key {
+            SortKey::Newest => Some(SortColumn::Date("start_date")),
+            SortKey::Price | SortKey::Size => None,
+        }
+    }
+
+    fn position(&self, _: SortKey) -> (SortValue, Uuid) {
+        (SortValue::Date(self.start_date), self.rent_transaction_id)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct RentTransactionForm {
     rent_property_id: Uuid,
@@ -202,84 +395,100 @@ struct RentTransactionForm {
 }
 
 #[post("/api/rent-transaction")]
-async fn post_rent_transaction(app_state: web::Data<AppState>, req: HttpRequest, rent_form: web::Json<RentTransactionForm>) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
-
+async fn post_rent_transaction(app_state: web::Data<AppState>, user_session: AuthSession, rent_form: web::Json<RentTransactionForm>) -> Result<HttpResponse, AppError> {
     let new_transaction_id = Uuid::new_v4();
 
-    let property = match sqlx::query_as!(
+    let property = sqlx::query_as!(
         RentProperty,
-        "SELECT * FROM rent_property WHERE rent_property_id = $1
-        AND rent_property_id not in 
-        (
-        SELECT rent_property_id FROM rent_transaction
-        WHERE status != 'Cancelled' AND end_date > CURRENT_DATE
-        )",
+        "SELECT * FROM rent_property WHERE rent_property_id = $1 AND status = 'Available'",
         rent_form.rent_property_id
-    ).fetch_one(&app_state.db_pool).await {
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => return  HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Property not found".to_string(), None, Some(format!("Error: No property matching id: {}", rent_form.rent_property_id)))
-            ),
-            _ => return  HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Unable to retrieve property".to_string(), None, Some(err.to_string()))
-            )
-        },
-        Ok(prop) => prop
-    };
-
-    let rent_length = rent_form.end_date - rent_form.start_date;
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No property matching id: {}", rent_form.rent_property_id))?;
 
-    let total_payment = property.monthly_rent * (rent_length.num_days() / 30);
+    let total_payment = quote_rent(property.monthly_rent, property.min_rent_days, rent_form.start_date, rent_form.end_date, Local::now().date_naive())
+        .map_err(AppError::BadRequest)?
+        .total_payment;
 
     let result = sqlx::query_as!(
         RentTransaction,
-        "INSERT INTO rent_transaction(rent_transaction_id, rent_property_id, user_id, total_payment, start_date, end_date, status)
-        VALUES($1, $2, $3, $4, $5, $6, \'Unpaid\') RETURNING *",
+        r#"INSERT INTO rent_transaction(rent_transaction_id, rent_property_id, user_id, total_payment, start_date, end_date, status, payment_deadline)
+        VALUES($1, $2, $3, $4, $5, $6, 'Unpaid', $7)
+        RETURNING rent_transaction_id, rent_property_id, user_id, total_payment, start_date, end_date, status AS "status: TransactionStatus", payment_deadline, cancelled_at, refund_amount"#,
         new_transaction_id,
         property.rent_property_id,
         user_session.user_data.user_id,
         total_payment,
         rent_form.start_date,
         rent_form.end_date,
+        Utc::now() + app_state.reservation_policy.payment_window
     ).fetch_one(&app_state.db_pool).await;
 
-    match result {
-        Err(_) => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false,"Failed submitting form".to_string(), None, Some("Server unable to process form submission".to_string()))
-        ),
-        Ok(rent) => return  HttpResponse::Ok().json(
-            ApiResponse::new(true, "Form submission success".to_string(), Some(rent), None)
-        )
-    }
+    let rent = match result {
+        // The exclusion constraint on rent_transaction rejects overlapping
+        // bookings, including ones racing this request.
+        Err(sqlx::Error::Database(db_err)) if db_err.code().as_deref() == Some(EXCLUSION_VIOLATION) => {
+            return Err(AppError::Conflict("Error: Property is already booked for part of the requested period".to_string()));
+        },
+        result => result?,
+    };
 
+    app_state.metrics.record_transaction(TransactionKind::Rent);
 
-    
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Form submission success".to_string(), Some(rent), None)
+    ))
 }
 
-#[get("/api/rent-transaction/{rent_transaction_id}")]
-async fn get_rent_transaction_by_id(app_state: web::Data<AppState>, req: HttpRequest, rent_transaction_id: web::Path<Uuid>) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
+#[derive(Debug, Serialize)]
+struct RentQuoteResponse {
+    rent_property_id: Uuid,
+    /// Whether the period is still free to book.
+    available: bool,
+    #[serde(flatten)]
+    quote: RentQuote,
+}
 
-    let result = sqlx::query_as!(
+/// Prices a rent period the way booking it would, without booking it.
+#[post("/api/rent-quote")]
+async fn post_rent_quote(app_state: web::Data<AppState>, rent_form: web::Json<RentTransactionForm>) -> Result<HttpResponse, AppError> {
+    let property = sqlx::query_as!(
+        RentProperty,
+        "SELECT * FROM rent_property WHERE rent_property_id = $1 AND status = 'Available'",
+        rent_form.rent_property_id
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No property matching id: {}", rent_form.rent_property_id))?;
+
+    let quote = quote_rent(property.monthly_rent, property.min_rent_days, rent_form.start_date, rent_form.end_date, Local::now().date_naive())
+        .map_err(AppError::BadRequest)?;
+
+    let is_booked = sqlx::query_scalar!(
+        "SELECT EXISTS(
+            SELECT 1 FROM rent_transaction
+            WHERE rent_property_id = $1 AND status != 'Cancelled'
+            AND daterange(start_date, end_date) && daterange($2, $3)
+        ) AS \"exists!\"",
+        property.rent_property_id,
+        rent_form.start_date,
+        rent_form.end_date
+    ).fetch_one(&app_state.db_pool).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(true, "Successfully priced rent period".to_string(), Some(RentQuoteResponse {
+        rent_property_id: property.rent_property_id,
+        available: !is_booked,
+        quote,
+    }), None)))
+}
+
+#[get("/api/rent-transaction/{rent_transaction_id}")]
+async fn get_rent_transaction_by_id(app_state: web::Data<AppState>, user_session: AuthSession, rent_transaction_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let transaction = sqlx::query_as!(
         RentTransactionObject,
-        "SELECT 
+        r#"SELECT 
         rt.rent_transaction_id,
         rt.user_id,
         rt.total_payment,
         rt.start_date,
         rt.end_date,
-        rt.status,
+        rt.status AS "status: TransactionStatus",
+        rt.payment_deadline,
         JSON_BUILD_OBJECT(
             'rent_property_id', rp.rent_property_id,
             'title', rp.title,
@@ -297,54 +506,31 @@ async fn get_rent_transaction_by_id(app_state: web::Data<AppState>, req: HttpReq
         FROM rent_transaction rt
         JOIN rent_property rp ON rt.rent_property_id = rp.rent_property_id
         WHERE rent_transaction_id = $1;
-        ",
+        "#,
         *rent_transaction_id
-    ).fetch_one(&app_state.db_pool).await;
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No transaction matching id: {}", *rent_transaction_id))?;
 
-    match result {
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Transaction not found".to_string(), None, Some(format!("Error: No transaction matching id: {}", *rent_transaction_id)))
-            ),
-            _ => HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Unable to retrieve transaction".to_string(), None, Some(err.to_string()))
-            )
-        },
-        Ok(transaction) => {
-            if transaction.user_id == user_session.user_data.user_id {
-                HttpResponse::Ok().json(
-                   ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
-                )
-            } else {
-                HttpResponse::Forbidden().json(
-                    ApiResponse::<()>::new(false, "Content restricted".to_string(), None, Some("User does not match transaction owner".to_string()))
-                )
-            }
-        }
+    if transaction.user_id != user_session.user_data.user_id {
+        return Err(AppError::Forbidden("User does not match transaction owner".to_string()));
     }
 
-
-    
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
+    ))
 }
 
 #[get("/api/my-rent-transaction")]
-async fn get_my_rent_transaction(app_state: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
-
-    let result = sqlx::query_as!(
-        RentTransactionObject,
-        "SELECT 
+async fn get_my_rent_transaction(app_state: web::Data<AppState>, user_session: AuthSession, page: Pagination<RentTransactionObject>) -> Result<HttpResponse, AppError> {
+    let user_id = user_session.user_data.user_id;
+    let transaction = page.fetch(&app_state.db_pool, |query| {
+        query.push("SELECT 
         rt.rent_transaction_id,
         rt.user_id,
         rt.total_payment,
         rt.start_date,
         rt.end_date,
         rt.status,
+        rt.payment_deadline,
         JSON_BUILD_OBJECT(
             'rent_property_id', rp.rent_property_id,
             'title', rp.title,
@@ -361,79 +547,111 @@ async fn get_my_rent_transaction(app_state: web::Data<AppState>, req: HttpReques
         ) AS rent_property
         FROM rent_transaction rt
         JOIN rent_property rp ON rt.rent_property_id = rp.rent_property_id
-        WHERE user_id = $1;
-        ",
-        user_session.user_data.user_id,
-    ).fetch_all(&app_state.db_pool).await;
+        WHERE user_id = ").push_bind(user_id);
+    }).await?;
 
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
+    ))
+}
 
-    match result {
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Transaction not found".to_string(), None, Some(format!("Error: No transaction for user id: {}", user_session.user_data.user_id)))
-            ),
-            _ => HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Unable to retrieve transaction".to_string(), None, Some(err.to_string()))
-            )
-        },
-        Ok(transaction) => {
-            HttpResponse::Ok().json(
-                   ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
-                )
-        }
+
+/// Locks a rent transaction for the rest of `conn`'s transaction, provided
+/// it belongs to `user_id`.
+async fn lock_own_rent_transaction(conn: &mut PgConnection, rent_transaction_id: Uuid, user_id: Uuid) -> Result<RentTransaction, AppError> {
+    let rent_transaction = sqlx::query_as!(
+        RentTransaction,
+        r#"SELECT rent_transaction_id, rent_property_id, user_id, total_payment, start_date, end_date, status AS "status: TransactionStatus", payment_deadline, cancelled_at, refund_amount
+        FROM rent_transaction WHERE rent_transaction_id = $1 FOR UPDATE"#,
+        rent_transaction_id
+    ).fetch_one(conn).await.or_not_found("Invalid transaction id")?;
+
+    if rent_transaction.user_id != user_id {
+        return Err(AppError::Forbidden("User not authorized".to_string()));
     }
-    
-    
-}
 
+    Ok(rent_transaction)
+}
 
+/// Opens a payment for a rent transaction. It is marked `Paid` once the
+/// payment provider confirms the payment.
 #[post("/api/pay-rent/{rent_transaction_id}")]
-async fn pay_rent(app_state: web::Data<AppState>, req: HttpRequest, rent_transaction_id: web::Path<Uuid>) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
+async fn pay_rent(app_state: web::Data<AppState>, user_session: AuthSession, rent_transaction_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let mut trx = app_state.db_pool.begin().await?;
 
-    let mut trx = match app_state.db_pool.begin().await {
-        Err(err) => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Internal Server Error".to_string(), None, Some(err.to_string()))
-        ),
+    let rent_transaction = lock_own_rent_transaction(&mut trx, *rent_transaction_id, user_session.user_data.user_id).await?;
 
-        Ok(tr) => tr
-    };
+    rent_transaction.status.transition(TransactionKind::Rent, TransactionStatus::Paid)
+        .and(check_payment_deadline(rent_transaction.payment_deadline))
+        .map_err(AppError::Conflict)?;
 
-    let result = sqlx::query_as!(RentTransaction,
-        "UPDATE rent_transaction SET status = 'Paid' WHERE rent_transaction_id = $1 returning *",
-        *rent_transaction_id
-    ).fetch_one(&mut *trx).await;
-
-    let rent_transaction = match result {
-        Err(err) => match err {
-        sqlx::Error::RowNotFound => return  HttpResponse::NotFound().json(
-            ApiResponse::<()>::new(false, "Failed processing payment".to_string(), None, Some("Invalid transaction id".to_string()))
-        ),
-        _ => return  HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed processing payment".to_string(), None, Some(err.to_string()))
-        )
-        },
-        Ok(trans) => trans,
-    };
+    let total_payment = rent_transaction.total_payment
+        .ok_or_else(|| AppError::Conflict("Error: Transaction has no total payment".to_string()))?;
 
-    if rent_transaction.user_id != user_session.user_data.user_id {
-        trx.rollback().await.unwrap();
+    let payment = start_payment(app_state.payment_provider.as_ref(), &mut trx, rent_transaction.user_id, PaymentTarget::Rent(rent_transaction.rent_transaction_id), total_payment).await?;
 
-        return HttpResponse::Forbidden().json(
-            ApiResponse::<()>::new(false, "Failed processing payment".to_string(), None, Some("User not authorized".to_string()))
-        );
+    trx.commit().await?;
+
+    Ok(HttpResponse::Accepted().json(
+        ApiResponse::new(true, "Payment pending".to_string(), Some(payment), None)
+    ))
+}
+
+/// Marks a rent transaction paid after its payment succeeded. Returns
+/// `false` if it was no longer waiting for payment.
+pub async fn confirm_rent_payment(conn: &mut PgConnection, rent_transaction_id: Uuid) -> sqlx::Result<bool> {
+    let status = sqlx::query_scalar!(
+        r#"SELECT status AS "status: TransactionStatus" FROM rent_transaction WHERE rent_transaction_id = $1 FOR UPDATE"#,
+        rent_transaction_id
+    ).fetch_optional(&mut *conn).await?;
+
+    if !status.is_some_and(|status| status.can_become(TransactionKind::Rent, TransactionStatus::Paid)) {
+        return Ok(false);
     }
 
-    trx.commit().await.unwrap();
+    sqlx::query!(
+        "UPDATE rent_transaction SET status = 'Paid' WHERE rent_transaction_id = $1",
+        rent_transaction_id
+    ).execute(conn).await?;
 
-    HttpResponse::Ok().json(
-        ApiResponse::new(true, "Payment processing success".to_string(), Some(rent_transaction), None)
-    )
+    Ok(true)
+}
+
+/// Cancels a rent transaction. Paid rent is refunded according to the
+/// cancellation policy, and only until the rent starts. A payment still
+/// pending is refunded in full if it later succeeds.
+#[post("/api/rent-transaction/{rent_transaction_id}/cancel")]
+async fn cancel_rent_transaction(app_state: web::Data<AppState>, session: AuthSession, rent_transaction_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let mut trx = app_state.db_pool.begin().await?;
+
+    let rent_transaction = lock_own_rent_transaction(&mut trx, *rent_transaction_id, session.user_data.user_id).await?;
+
+    rent_transaction.status.transition(TransactionKind::Rent, TransactionStatus::Cancelled).map_err(AppError::Conflict)?;
+
+    let refund_amount = if rent_transaction.status == TransactionStatus::Paid {
+        app_state.cancellation_policy.rent_refund(
+            rent_transaction.total_payment.unwrap_or(0),
+            rent_transaction.start_date,
+            Local::now().date_naive()
+        ).map_err(AppError::Conflict)?
+    } else {
+        0
+    };
+
+    let rent_transaction = sqlx::query_as!(
+        RentTransaction,
+        r#"UPDATE rent_transaction SET status = 'Cancelled', cancelled_at = NOW(), refund_amount = $2
+        WHERE rent_transaction_id = $1
+        RETURNING rent_transaction_id, rent_property_id, user_id, total_payment, start_date, end_date, status AS "status: TransactionStatus", payment_deadline, cancelled_at, refund_amount"#,
+        *rent_transaction_id,
+        refund_amount
+    ).fetch_one(&mut *trx).await?;
+
+    trx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Transaction cancelled".to_string(), Some(rent_transaction), None)
+    ))
 }
 
 pub fn init_routes(cfg: &mut ServiceConfig) {
@@ -441,8 +659,53 @@ pub fn init_routes(cfg: &mut ServiceConfig) {
         .service(add_rent_property)
         .service(get_rent_properties)
         .service(get_rent_property_by_id)
+        .service(get_rent_availability)
+        .service(update_rent_property)
+        .service(delete_rent_property)
         .service(post_rent_transaction)
+        .service(post_rent_quote)
         .service(get_rent_transaction_by_id)
         .service(get_my_rent_transaction)
-        .service(pay_rent);
-}
\ No newline at end of file
+        .service(pay_rent)
+        .service(cancel_rent_transaction);
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 6, day).unwrap()
+    }
+
+    fn range(start: u32, end: u32) -> DateRange {
+        DateRange { start_date: date(start), end_date: date(end) }
+    }
+
+    #[test]
+    fn unbooked_window_is_free() {
+        assert_eq!(free_ranges(date(1), date(30), &[]), vec![range(1, 30)]);
+    }
+
+    #[test]
+    fn adjacent_bookings_leave_no_gap_between_them() {
+        assert_eq!(free_ranges(date(1), date(30), &[range(5, 10), range(10, 15)]), vec![range(1, 5), range(15, 30)]);
+    }
+
+    #[test]
+    fn overlapping_bookings_are_merged() {
+        assert_eq!(free_ranges(date(1), date(30), &[range(5, 12), range(8, 10), range(11, 15)]), vec![range(1, 5), range(15, 30)]);
+    }
+
+    #[test]
+    fn bookings_at_the_window_edges() {
+        assert_eq!(free_ranges(date(1), date(30), &[range(1, 5)]), vec![range(5, 30)]);
+        assert_eq!(free_ranges(date(1), date(30), &[range(25, 30)]), vec![range(1, 25)]);
+        assert_eq!(free_ranges(date(1), date(30), &[range(1, 30)]), vec![]);
+    }
+
+    #[test]
+    fn open_ended_booking_takes_the_rest_of_the_window() {
+        // The query clips a booking without an end date to the window's end.
+        assert_eq!(free_ranges(date(1), date(30), &[range(3, 6), range(20, 30)]), vec![range(1, 3), range(6, 20)]);
+    }
+}
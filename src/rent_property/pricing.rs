@@ -0,0 +1,151 @@
+use chrono::{Datelike, Months, NaiveDate};
+use serde::Serialize;
+
+/// Price of a rent period, itemised.
+///
+/// Whole calendar months from `start_date` cost `monthly_rent` each. Any
+/// remaining days are charged pro rata against the length of the calendar
+/// month they fall in, rounded to the nearest unit.
+#[derive(Debug, Serialize)]
+pub struct RentQuote {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub days: i64,
+    pub monthly_rent: i64,
+    pub full_months: u32,
+    pub full_months_price: i64,
+    pub extra_days: i64,
+    /// Length of the month the extra days are prorated against.
+    pub extra_days_month_length: i64,
+    pub extra_days_price: i64,
+    pub total_payment: i64,
+}
+
+/// Prices renting from `start_date` up to, but not including, `end_date`.
+pub fn quote_rent(monthly_rent: i64, min_rent_days: i32, start_date: NaiveDate, end_date: NaiveDate, today: NaiveDate) -> Result<RentQuote, String> {
+    if start_date < today {
+        return Err("Error: start_date must not be in the past".to_string());
+    }
+
+    if end_date <= start_date {
+        return Err("Error: end_date must be after start_date".to_string());
+    }
+
+    let days = (end_date - start_date).num_days();
+    if days < i64::from(min_rent_days) {
+        return Err(format!("Error: This property must be rented for at least {} days", min_rent_days));
+    }
+
+    // Months are always counted from the start date so a period starting on
+    // the 31st doesn't drift once it passes a shorter month.
+    let month_after = |months: u32| start_date.checked_add_months(Months::new(months));
+    let too_long = || "Error: Rent period is too long".to_string();
+
+    // Counting calendar months overshoots by one when end_date falls earlier
+    // in its month than start_date does in its own.
+    let month_span = (end_date.year() - start_date.year()) * 12 + end_date.month() as i32 - start_date.month() as i32;
+    let mut full_months = month_span as u32;
+    if month_after(full_months).ok_or_else(too_long)? > end_date {
+        full_months -= 1;
+    }
+
+    let anniversary = month_after(full_months).ok_or_else(too_long)?;
+    let extra_days = (end_date - anniversary).num_days();
+    let extra_days_month_length = (month_after(full_months + 1).ok_or_else(too_long)? - anniversary).num_days();
+
+    let full_months_price = monthly_rent.checked_mul(i64::from(full_months)).ok_or_else(too_long)?;
+    let extra_days_price = ((i128::from(monthly_rent) * i128::from(extra_days) + i128::from(extra_days_month_length) / 2)
+        / i128::from(extra_days_month_length)) as i64;
+    let total_payment = full_months_price.checked_add(extra_days_price).ok_or_else(too_long)?;
+
+    Ok(RentQuote {
+        start_date,
+        end_date,
+        days,
+        monthly_rent,
+        full_months,
+        full_months_price,
+        extra_days,
+        extra_days_month_length,
+        extra_days_price,
+        total_payment,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn quote(start_date: NaiveDate, end_date: NaiveDate) -> RentQuote {
+        quote_rent(3_100, 1, start_date, end_date, start_date).unwrap()
+    }
+
+    #[test]
+    fn month_end_start_counts_shorter_month_as_full() {
+        let quote = quote(date(2025, 1, 31), date(2025, 2, 28));
+        assert_eq!(quote.full_months, 1);
+        assert_eq!(quote.extra_days, 0);
+        assert_eq!(quote.total_payment, 3_100);
+    }
+
+    #[test]
+    fn month_end_start_prorates_days_past_shorter_month() {
+        let quote = quote(date(2025, 1, 31), date(2025, 3, 1));
+        assert_eq!(quote.full_months, 1);
+        assert_eq!(quote.extra_days, 1);
+        assert_eq!(quote.extra_days_month_length, 31);
+        assert_eq!(quote.extra_days_price, 100);
+        assert_eq!(quote.total_payment, 3_200);
+    }
+
+    #[test]
+    fn month_end_start_does_not_drift() {
+        let quote = quote(date(2025, 1, 31), date(2025, 3, 31));
+        assert_eq!(quote.full_months, 2);
+        assert_eq!(quote.extra_days, 0);
+    }
+
+    #[test]
+    fn leap_day_is_a_full_month_in_leap_years() {
+        let quote = quote(date(2024, 1, 31), date(2024, 2, 29));
+        assert_eq!(quote.full_months, 1);
+        assert_eq!(quote.extra_days, 0);
+
+        let quote = quote_rent(2_900, 1, date(2024, 2, 1), date(2024, 2, 15), date(2024, 2, 1)).unwrap();
+        assert_eq!(quote.full_months, 0);
+        assert_eq!(quote.extra_days, 14);
+        assert_eq!(quote.extra_days_month_length, 29);
+        assert_eq!(quote.total_payment, 1_400);
+    }
+
+    #[test]
+    fn leap_day_start_ends_a_year_later_on_february_28th() {
+        let quote = quote(date(2024, 2, 29), date(2025, 2, 28));
+        assert_eq!(quote.full_months, 12);
+        assert_eq!(quote.extra_days, 0);
+        assert_eq!(quote.total_payment, 37_200);
+    }
+
+    #[test]
+    fn period_shorter_than_minimum_is_rejected() {
+        let start = date(2025, 6, 1);
+        assert!(quote_rent(3_000, 30, start, date(2025, 6, 30), start).is_err());
+        assert!(quote_rent(3_000, 30, start, date(2025, 7, 1), start).is_ok());
+    }
+
+    #[test]
+    fn end_not_after_start_is_rejected() {
+        let start = date(2025, 6, 1);
+        assert!(quote_rent(3_000, 1, start, start, start).is_err());
+        assert!(quote_rent(3_000, 1, start, date(2025, 5, 31), start).is_err());
+    }
+
+    #[test]
+    fn start_in_the_past_is_rejected() {
+        assert!(quote_rent(3_000, 1, date(2025, 6, 1), date(2025, 7, 1), date(2025, 6, 2)).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection};
+use uuid::Uuid;
+
+use crate::transaction::TransactionStatus;
+
+use super::mortgage::MortgagePlan;
+
+/// One month of a sale's installment ledger.
+///
+/// `status` is `Unpaid`, `Paid` or `Cancelled` as stored, reported as
+/// `Overdue` while an unpaid installment is past its due date.
+#[derive(Debug, Serialize, FromRow)]
+pub struct SaleInstallment {
+    pub sale_transaction_id: Uuid,
+    pub installment_number: i32,
+    pub due_date: NaiveDate,
+    pub amount: i64,
+    pub principal: i64,
+    pub interest: i64,
+    /// Loan left once this installment is paid.
+    pub balance: i64,
+    pub status: String,
+    pub paid_at: Option<DateTime<Utc>>,
+}
+
+/// Ledger of a sale with totals of what is settled and what is owed.
+#[derive(Debug, Serialize)]
+pub struct InstallmentLedger {
+    pub sale_transaction_id: Uuid,
+    pub status: TransactionStatus,
+    pub down_payment: i64,
+    pub down_payment_paid_at: Option<DateTime<Utc>>,
+    pub paid_installments: usize,
+    pub overdue_installments: usize,
+    /// Sum of every unpaid installment, overdue or not.
+    pub outstanding_amount: i64,
+    pub next_due_date: Option<NaiveDate>,
+    pub installments: Vec<SaleInstallment>,
+}
+
+impl InstallmentLedger {
+    pub fn new(sale_transaction_id: Uuid, status: TransactionStatus, down_payment: i64, down_payment_paid_at: Option<DateTime<Utc>>, installments: Vec<SaleInstallment>) -> Self {
+        let unpaid = || installments.iter().filter(|installment| installment.status == "Unpaid" || installment.status == "Overdue");
+
+        InstallmentLedger {
+            sale_transaction_id,
+            status,
+            down_payment,
+            down_payment_paid_at,
+            paid_installments: installments.iter().filter(|installment| installment.status == "Paid").count(),
+            overdue_installments: unpaid().filter(|installment| installment.status == "Overdue").count(),
+            outstanding_amount: unpaid().map(|installment| installment.amount).sum(),
+            next_due_date: unpaid().map(|installment| installment.due_date).next(),
+            installments,
+        }
+    }
+}
+
+/// Writes the installments of `plan` as the ledger of a new sale.
+pub async fn insert_ledger(conn: &mut PgConnection, sale_transaction_id: Uuid, plan: &MortgagePlan) -> sqlx::Result<()> {
+    let schedule = &plan.schedule;
+
+    sqlx::query!(
+        "INSERT INTO sale_installment(sale_transaction_id, installment_number, due_date, amount, principal, interest, balance)
+        SELECT $1, * FROM UNNEST($2::INT[], $3::DATE[], $4::BIGINT[], $5::BIGINT[], $6::BIGINT[], $7::BIGINT[])",
+        sale_transaction_id,
+        &schedule.iter().map(|installment| installment.month).collect::<Vec<_>>(),
+        &schedule.iter().map(|installment| installment.due_date).collect::<Vec<_>>(),
+        &schedule.iter().map(|installment| installment.payment).collect::<Vec<_>>(),
+        &schedule.iter().map(|installment| installment.principal).collect::<Vec<_>>(),
+        &schedule.iter().map(|installment| installment.interest).collect::<Vec<_>>(),
+        &schedule.iter().map(|installment| installment.balance).collect::<Vec<_>>(),
+    ).execute(conn).await?;
+
+    Ok(())
+}
+
+/// Installments of a sale in order, with overdue ones flagged.
+pub async fn get_ledger(conn: &mut PgConnection, sale_transaction_id: Uuid) -> sqlx::Result<Vec<SaleInstallment>> {
+    sqlx::query_as!(
+        SaleInstallment,
+        r#"SELECT sale_transaction_id, installment_number, due_date, amount, principal, interest, balance, paid_at,
+        CASE WHEN status = 'Unpaid' AND due_date < CURRENT_DATE THEN 'Overdue' ELSE status END AS "status!"
+        FROM sale_installment WHERE sale_transaction_id = $1 ORDER BY installment_number"#,
+        sale_transaction_id
+    ).fetch_all(conn).await
+}
+
+/// Number of installments of a sale still to be paid.
+pub async fn count_unpaid(conn: &mut PgConnection, sale_transaction_id: Uuid) -> sqlx::Result<i64> {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM sale_installment WHERE sale_transaction_id = $1 AND status = 'Unpaid'"#,
+        sale_transaction_id
+    ).fetch_one(conn).await
+}
@@ -1,23 +1,25 @@
 
 
-use std::env;
-
 use actix_multipart::form::{tempfile::TempFile, text::Text, MultipartForm};
-use actix_web::{get, post, web::{self, ServiceConfig}, HttpRequest, HttpResponse, Responder};
-use chrono::NaiveDate;
+use actix_web::{delete, get, patch, post, web::{self, ServiceConfig}, HttpResponse};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use installment::{count_unpaid, get_ledger, insert_ledger, InstallmentLedger};
+use mortgage::{plan_mortgage, MortgagePlan};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use slug::slugify;
-use sqlx::{prelude::FromRow};
+use sqlx::{prelude::FromRow, PgConnection};
 use uuid::Uuid;
 
-use crate::{utils::{get_session, models::ApiResponse, save_uploaded_file}, AppState};
+use crate::{listing::ListingFilter, owner::resolve_listing_owner, pagination::{Paginated, Pagination, SortColumn, SortKey, SortValue}, payment::{start_payment, PaymentTarget}, property_picture::{attach_pictures, authorize_property_edit, insert_pictures, replace_pictures, store_uploaded_pictures, PropertyKind, WithPictures}, transaction::{check_payment_deadline, TransactionKind, TransactionStatus}, utils::{auth::{AuthSession, OwnerSession}, error::{AppError, OrNotFound}, models::ApiResponse}, AppState};
+
+pub mod installment;
+pub mod mortgage;
 
 #[derive(Debug, MultipartForm)]
 struct SaleUploadForm {
     #[multipart(rename = "picture")]
-    picture: TempFile,
-    owner: Text<String>,
+    picture: Vec<TempFile>,
+    owner: Option<Text<String>>,
     title: Text<String>,
     description: Text<String>,
     address: Text<String>,
@@ -28,74 +30,73 @@ struct SaleUploadForm {
     property_price: Text<i64>
 }
 
+/// Every field is optional; only the ones sent are changed. Sending any
+/// picture replaces the whole gallery.
+#[derive(Debug, MultipartForm)]
+struct SaleUpdateForm {
+    #[multipart(rename = "picture")]
+    picture: Vec<TempFile>,
+    title: Option<Text<String>>,
+    description: Option<Text<String>>,
+    address: Option<Text<String>>,
+    lt: Option<Text<i32>>,
+    lb: Option<Text<i32>>,
+    bedroom: Option<Text<i16>>,
+    bathroom: Option<Text<i16>>,
+    property_price: Option<Text<i64>>,
+    status: Option<Text<String>>
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 struct SaleProperty {
     sale_property_id: Uuid,
     title: String,
     description: String,
     address: String,
-    owner_id: String,
+    owner_id: Uuid,
     lt: i32,
     lb: i32,
     bedroom: i16,
     bathroom: i16,
     property_price: i64,
     picture_url: String,
-    status: String
+    status: String,
+    created_at: DateTime<Utc>,
 }
 
-#[post("/api/sale-property")]
-async fn add_sale_property(app_state: web::Data<AppState>, mp: MultipartForm<SaleUploadForm>) -> impl Responder {
-    let host_url = env::var("HOST_URL").expect("Please provide HOST_URL");
-    let picture_name = match mp.picture.file_name.clone() {
-        Some(name) => {
-            let file_path : Vec<&str> = name.split(".").collect();
-            format!("{}.{}", slugify(file_path[0]), file_path[file_path.len() - 1])
-        },
-        None => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Uploaded file error".to_string(), None, Some("Error: Unable to get file name".to_string()))
-        )
-    };
+impl Paginated for SaleProperty {
+    const ID_COLUMN: &'static str = "sale_property_id";
 
-    let file_path = format!("./uploaded/sales/{}", picture_name);
-    let url_path = format!("{}/sale-pictures/{}", host_url, picture_name);
-
-    // check extension of file
-    match infer::get_from_path(mp.picture.file.path()) {
-        Err(err) => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed reading uploaded file".to_string(), None, Some(err.to_string()))
-        ),
-        Ok(path) => match path {
-            None => return HttpResponse::BadRequest().json(
-                ApiResponse::<()>::new(false, "Unable to read file type".to_string(), None, Some("Error: File type unknown".to_string()))
-            ),
-            Some(kind) => {
-                if (kind.mime_type() == "image/jpeg" && (kind.extension() == "jpg" || kind.extension() == "jpeg")) || (kind.mime_type() == "image/png" && kind.extension() == "png") {
-                    println!("Image extension valid");
-                } else {
-                    return HttpResponse::BadRequest().json(
-                        ApiResponse::<()>::new(false, "Invalid file extension".to_string(), None, Some("Invalid file type. Picture must be in [.jpg, .jpeg, .png]".to_string()))
-                    );
-                }
-            }
-        }
+    fn sort_column(key: SortKey) -> Option<SortColumn> {
+        Some(match key {
+            SortKey::Newest => SortColumn::Time("created_at"),
+            SortKey::Price => SortColumn::Number("property_price"),
+            SortKey::Size => SortColumn::Number("lb"),
+        })
     }
 
-    if let Err(err) = save_uploaded_file(&mp.picture, &file_path).await {
-        return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed saving file".to_string(), None, Some(err.to_string()))
-        );
-    };
+    fn position(&self, key: SortKey) -> (SortValue, Uuid) {
+        let value = match key {
+            SortKey::Newest => SortValue::Time(self.created_at),
+            SortKey::Price => SortValue::Number(self.property_price),
+            SortKey::Size => SortValue::Number(self.lb.into()),
+        };
+
+        (value, self.sale_property_id)
+    }
+}
+
+#[post("/api/sale-property")]
+async fn add_sale_property(app_state: web::Data<AppState>, session: OwnerSession, mp: MultipartForm<SaleUploadForm>) -> Result<HttpResponse, AppError> {
+    let owner_id = resolve_listing_owner(&app_state.db_pool, &session, mp.owner.as_deref().map(String::as_str)).await?;
+
+    let pictures = store_uploaded_pictures(app_state.storage.as_ref(), &app_state.metrics, PropertyKind::Sale, &mp.picture).await?;
+
+    let mut trx = app_state.db_pool.begin().await?;
 
     let property_id = Uuid::new_v4();
-    let owner_id = match Uuid::parse_str(&mp.owner) {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Failed converting owner ID".to_string(), None, Some("Error: Invalid UUID format on owner_id".to_string()))
-        )
-    };
 
-    let result = sqlx::query_as!(
+    let property = sqlx::query_as!(
         SaleProperty,
         "INSERT INTO sale_property(sale_property_id, title, description, address, owner_id, lt, lb, bedroom, bathroom, property_price, picture_url, status)
             VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, 'Available')
@@ -110,65 +111,145 @@ async fn add_sale_property(app_state: web::Data<AppState>, mp: MultipartForm<Sal
             *mp.bedroom,
             *mp.bathroom,
             *mp.property_price,
-            url_path
-    ).fetch_one(&app_state.db_pool).await;
+            pictures[0].picture_url
+    ).fetch_one(&mut *trx).await?;
 
-    match result {
-        Ok(property) => HttpResponse::Ok().json(ApiResponse::new(true, "Successfully inserted sale property".to_string(), Some(property), None)),
-        Err(err) => HttpResponse::InternalServerError().json(ApiResponse::<()>::new(false, "Failed inserting sale property".to_string(), None, Some(err.to_string())))
-    }
+    let pictures = insert_pictures(&mut trx, PropertyKind::Sale, property_id, &pictures).await?;
+
+    trx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(true, "Successfully inserted sale property".to_string(), Some(WithPictures { property, pictures }), None)))
 }
 
 #[get("/api/sale-property")]
-async fn get_sale_properties(app_state: web::Data<AppState>) -> impl Responder {
-    let result = sqlx::query_as!(
-        SaleProperty,
-        "SELECT * FROM sale_property WHERE sale_property_id NOT IN 
-        (
-        SELECT sale_property_id FROM sale_transaction WHERE status != 'Cancelled'
-        )"
-    ).fetch_all(&app_state.db_pool).await;
-
-    match result {
-        Ok(properties) => HttpResponse::Ok().json(
-            ApiResponse::new(true, "Successfully fetch sale property".to_string(), Some(properties), None)
-        ),
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Failed fetching sale property".to_string(), None, Some("Error: No sale property found".to_string()))
-            ),
-            _ => HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Failed fetching sale property".to_string(), None, Some(err.to_string()))
-            )
-        }
-    }
+async fn get_sale_properties(app_state: web::Data<AppState>, session: Option<AuthSession>, filter: web::Query<ListingFilter>, page: Pagination<SaleProperty>) -> Result<HttpResponse, AppError> {
+    let mut filter = filter.into_inner();
+
+    filter.authorize(&app_state.db_pool, session.as_deref()).await?;
+
+    let mut properties = page.fetch(&app_state.db_pool, |query| {
+        query.push(
+            "SELECT * FROM sale_property WHERE sale_property_id NOT IN 
+            (
+            SELECT sale_property_id FROM sale_transaction WHERE status != 'Cancelled'
+            )"
+        );
+        filter.push_conditions(query, "property_price");
+    }).await?;
+
+    let items = attach_pictures(&app_state.db_pool, std::mem::take(&mut properties.items), |property| property.sale_property_id).await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully fetch sale property".to_string(), Some(properties.with_items(items)), None)
+    ))
 }
 
 #[get("/api/sale-property/{sale_property_id}")]
-async fn get_sale_property_by_id(app_state: web::Data<AppState>, sale_proerty_id: web::Path<Uuid>) -> impl Responder {
-    let result = sqlx::query_as!(
+async fn get_sale_property_by_id(app_state: web::Data<AppState>, sale_proerty_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let property = sqlx::query_as!(
         SaleProperty,
-        "SELECT * FROM sale_property WHERE sale_property_id = $1 AND sale_property_id NOT IN 
+        "SELECT * FROM sale_property WHERE sale_property_id = $1 AND status = 'Available' AND sale_property_id NOT IN 
         (
         SELECT sale_property_id FROM sale_transaction WHERE status != 'Cancelled'
         )",
         *sale_proerty_id
-    ).fetch_one(&app_state.db_pool).await;
-
-    match result {
-        Ok(property) => HttpResponse::Ok().json(
-            ApiResponse::new(true, "Successfully Retrieved Property".to_string(), Some(property), None)
-        ),
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Failed Fetching Property".to_string(), None, Some(format!("Error: No property matching id: {}", *sale_proerty_id)))
-            ),
-            _ => HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Failed to fetch property".to_string(), None, Some(err.to_string()))
-            )
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No property matching id: {}", *sale_proerty_id))?;
+
+    let property = attach_pictures(&app_state.db_pool, vec![property], |property| property.sale_property_id).await?.remove(0);
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully Retrieved Property".to_string(), Some(property), None)
+    ))
+}
+
+#[patch("/api/sale-property/{sale_property_id}")]
+async fn update_sale_property(app_state: web::Data<AppState>, session: OwnerSession, sale_property_id: web::Path<Uuid>, mp: MultipartForm<SaleUpdateForm>) -> Result<HttpResponse, AppError> {
+    let property_id = *sale_property_id;
+
+    authorize_property_edit(&app_state.db_pool, &session, PropertyKind::Sale, property_id).await?;
+
+    // Deleting goes through DELETE, so listings can only be hidden or shown here.
+    if let Some(status) = &mp.status {
+        if !["Available", "Unlisted"].contains(&status.as_str()) {
+            return Err(AppError::BadRequest("Error: status must be Available or Unlisted".to_string()));
         }
     }
-    
+
+    let pictures = if mp.picture.is_empty() {
+        None
+    } else {
+        Some(store_uploaded_pictures(app_state.storage.as_ref(), &app_state.metrics, PropertyKind::Sale, &mp.picture).await?)
+    };
+
+    let mut trx = app_state.db_pool.begin().await?;
+
+    if let Some(pictures) = &pictures {
+        replace_pictures(&mut trx, PropertyKind::Sale, property_id, pictures).await?;
+    }
+
+    let property = sqlx::query_as!(
+        SaleProperty,
+        "UPDATE sale_property SET
+            title = COALESCE($2, title),
+            description = COALESCE($3, description),
+            address = COALESCE($4, address),
+            lt = COALESCE($5, lt),
+            lb = COALESCE($6, lb),
+            bedroom = COALESCE($7, bedroom),
+            bathroom = COALESCE($8, bathroom),
+            property_price = COALESCE($9, property_price),
+            status = COALESCE($10, status)
+        WHERE sale_property_id = $1 AND status != 'Deleted'
+        RETURNING *",
+        property_id,
+        mp.title.as_deref(),
+        mp.description.as_deref(),
+        mp.address.as_deref(),
+        mp.lt.as_deref(),
+        mp.lb.as_deref(),
+        mp.bedroom.as_deref(),
+        mp.bathroom.as_deref(),
+        mp.property_price.as_deref(),
+        mp.status.as_deref()
+    ).fetch_one(&mut *trx).await?;
+
+    trx.commit().await?;
+
+    let property = attach_pictures(&app_state.db_pool, vec![property], |property| property.sale_property_id).await?.remove(0);
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully updated property".to_string(), Some(property), None)
+    ))
+}
+
+/// Soft-deletes a listing. The row and its pictures stay so past
+/// transactions still resolve, but it is hidden everywhere.
+#[delete("/api/sale-property/{sale_property_id}")]
+async fn delete_sale_property(app_state: web::Data<AppState>, session: OwnerSession, sale_property_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let property_id = *sale_property_id;
+
+    authorize_property_edit(&app_state.db_pool, &session, PropertyKind::Sale, property_id).await?;
+
+    let is_sold = sqlx::query_scalar!(
+        "SELECT EXISTS(
+            SELECT 1 FROM sale_transaction WHERE sale_property_id = $1 AND status != 'Cancelled'
+        ) AS \"exists!\"",
+        property_id
+    ).fetch_one(&app_state.db_pool).await?;
+
+    if is_sold {
+        return Err(AppError::Conflict("Error: Property has an ongoing sale transaction".to_string()));
+    }
+
+    let property = sqlx::query_as!(
+        SaleProperty,
+        "UPDATE sale_property SET status = 'Deleted' WHERE sale_property_id = $1 AND status != 'Deleted' RETURNING *",
+        property_id
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No property matching id: {}", property_id))?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully deleted property".to_string(), Some(property), None)
+    ))
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -180,7 +261,12 @@ struct SaleTransaction {
     installment_duration: i32,
     monthly_mortgage: i64,
     sale_date: NaiveDate,
-    status: String,
+    status: TransactionStatus,
+    /// When an unpaid transaction is cancelled if still unpaid.
+    payment_deadline: Option<DateTime<Utc>>,
+    down_payment_paid_at: Option<DateTime<Utc>>,
+    cancelled_at: Option<DateTime<Utc>>,
+    refund_amount: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -198,92 +284,114 @@ struct SaleTransactionObject {
     installment_duration: i32,
     monthly_mortgage: i64,
     sale_date: NaiveDate,
-    status: String,
+    status: TransactionStatus,
+    payment_deadline: Option<DateTime<Utc>>,
     sale_property: Value,
 }
 
-#[post("/api/sale-transaction")]
-async fn post_sale_transaction(app_state: web::Data<AppState>, req: HttpRequest, sale_form: web::Json<SaleForm>) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
+impl Paginated for SaleTransactionObject {
+    const ID_COLUMN: &'static str = "sale_transaction_id";
+
+    fn sort_column(key: SortKey) -> Option<SortColumn> {
+        match key {
+            SortKey::Newest => Some(SortColumn::Date("sale_date")),
+            SortKey::Price | SortKey::Size => None,
+        }
+    }
 
-    let property = match sqlx::query_as!(
+    fn position(&self, _: SortKey) -> (SortValue, Uuid) {
+        (SortValue::Date(self.sale_date), self.sale_transaction_id)
+    }
+}
+
+#[post("/api/sale-transaction")]
+async fn post_sale_transaction(app_state: web::Data<AppState>, user_session: AuthSession, sale_form: web::Json<SaleForm>) -> Result<HttpResponse, AppError> {
+    let property = sqlx::query_as!(
         SaleProperty,
-        "SELECT * FROM sale_property WHERE sale_property_id = $1 AND sale_property_id NOT IN 
+        "SELECT * FROM sale_property WHERE sale_property_id = $1 AND status = 'Available' AND sale_property_id NOT IN 
         (
         SELECT sale_property_id FROM sale_transaction WHERE status != 'Cancelled'
         )",
         sale_form.sale_property_id
-    ).fetch_one(&app_state.db_pool).await {
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => return  HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Failed fetching sale property".to_string(), None, Some("Error: No sale property found".to_string()))
-            ),
-            _ => return  HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Failed fetching sale property".to_string(), None, Some(err.to_string()))
-            )
-        },
-        Ok(prop) => prop,
-    };
+    ).fetch_one(&app_state.db_pool).await.or_not_found("Error: No sale property found")?;
 
-    let new_transaction_id = Uuid::new_v4();
+    let sale_date = Local::now().date_naive();
+    let plan = plan_mortgage(&app_state.mortgage_policy, property.property_price, sale_form.down_payment, sale_form.installment_duration, sale_date)
+        .map_err(AppError::BadRequest)?;
 
-    let monthly_mortgage = calculate_monthly_mortgage(property.property_price, sale_form.down_payment, sale_form.installment_duration, 0.06);
+    let new_transaction_id = Uuid::new_v4();
 
+    let mut trx = app_state.db_pool.begin().await?;
 
-    let result = sqlx::query_as!(
+    let sale = sqlx::query_as!(
         SaleTransaction,
-        "INSERT INTO sale_transaction(sale_transaction_id, sale_property_id, user_id, down_payment, installment_duration, monthly_mortgage, sale_date, status)
-        VALUES($1, $2, $3, $4, $5, $6, CURRENT_DATE, 'Unpaid') RETURNING *",
+        r#"INSERT INTO sale_transaction(sale_transaction_id, sale_property_id, user_id, down_payment, installment_duration, monthly_mortgage, sale_date, status, payment_deadline)
+        VALUES($1, $2, $3, $4, $5, $6, $7, 'Unpaid', $8)
+        RETURNING sale_transaction_id, sale_property_id, user_id, down_payment, installment_duration, monthly_mortgage, sale_date, status AS "status: TransactionStatus", payment_deadline, down_payment_paid_at, cancelled_at, refund_amount"#,
         new_transaction_id,
         sale_form.sale_property_id,
         user_session.user_data.user_id,
         sale_form.down_payment,
         sale_form.installment_duration,
-        monthly_mortgage
-    ).fetch_one(&app_state.db_pool).await;
-
-    match result {
-        Err(_) => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false,"Failed submitting form".to_string(), None, Some("Server unable to process form submission".to_string()))
-        ),
-        Ok(sale) => return  HttpResponse::Ok().json(
-            ApiResponse::new(true, "Form submission success".to_string(), Some(sale), None)
-        )
-    }
-}
+        plan.monthly_mortgage,
+        sale_date,
+        Utc::now() + app_state.reservation_policy.payment_window
+    ).fetch_one(&mut *trx).await?;
 
+    insert_ledger(&mut trx, new_transaction_id, &plan).await?;
 
-fn calculate_monthly_mortgage(total_loan: i64, down_payment: i64, loan_duration: i32, interest_rate: f64) -> i64 {
-    let principal = total_loan - down_payment;
-    let monthly_rate = interest_rate / 12.0;
+    trx.commit().await?;
 
-    (principal as f64 * monthly_rate * (1.0 + monthly_rate).powf(loan_duration as f64) / ((1.0 + monthly_rate).powf(loan_duration as f64) - 1.0)) as i64
+    app_state.metrics.record_transaction(TransactionKind::Sale);
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Form submission success".to_string(), Some(sale), None)
+    ))
 }
 
-#[get("/api/sale-transaction/{sale_transaction_id}")]
-async fn get_sale_transaction_by_id(app_state: web::Data<AppState>, req: HttpRequest, sale_transaction_id: web::Path<Uuid>) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
+#[derive(Debug, Deserialize)]
+struct MortgageSimulationForm {
+    /// Price the mortgage on a listed property, or on `property_price`.
+    sale_property_id: Option<Uuid>,
+    property_price: Option<i64>,
+    down_payment: i64,
+    installment_duration: i32,
+}
+
+/// Builds the amortization schedule buying a property would get, without
+/// buying it.
+#[post("/api/mortgage-simulation")]
+async fn post_mortgage_simulation(app_state: web::Data<AppState>, form: web::Json<MortgageSimulationForm>) -> Result<HttpResponse, AppError> {
+    let property_price = match (form.sale_property_id, form.property_price) {
+        (Some(sale_property_id), None) => sqlx::query_scalar!(
+            "SELECT property_price FROM sale_property WHERE sale_property_id = $1 AND status = 'Available'",
+            sale_property_id
+        ).fetch_one(&app_state.db_pool).await.or_not_found("Error: No sale property found")?,
+        (None, Some(price)) => price,
+        _ => return Err(AppError::BadRequest("Error: Provide either sale_property_id or property_price".to_string())),
     };
 
-    let result = sqlx::query_as!(
+    let plan: MortgagePlan = plan_mortgage(&app_state.mortgage_policy, property_price, form.down_payment, form.installment_duration, Local::now().date_naive())
+        .map_err(AppError::BadRequest)?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully simulated mortgage".to_string(), Some(plan), None)
+    ))
+}
+
+#[get("/api/sale-transaction/{sale_transaction_id}")]
+async fn get_sale_transaction_by_id(app_state: web::Data<AppState>, user_session: AuthSession, sale_transaction_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let transaction = sqlx::query_as!(
         SaleTransactionObject,
-        "SELECT 
+        r#"SELECT 
         st.sale_transaction_id,
         st.user_id,
         st.down_payment,
         st.installment_duration,
 		st.monthly_mortgage,
         st.sale_date,
-        st.status,
+        st.status AS "status: TransactionStatus",
+        st.payment_deadline,
         JSON_BUILD_OBJECT(
             'sale_property_id', sp.sale_property_id,
             'title', sp.title,
@@ -300,45 +408,24 @@ async fn get_sale_transaction_by_id(app_state: web::Data<AppState>, req: HttpReq
         ) AS sale_property
         FROM sale_transaction st
         JOIN sale_property sp ON st.sale_property_id = sp.sale_property_id
-        WHERE sale_transaction_id = $1",
+        WHERE sale_transaction_id = $1"#,
         *sale_transaction_id
-    ).fetch_one(&app_state.db_pool).await;
-    
-    match result {
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Transaction not found".to_string(), None, Some(format!("Error: No transaction matching id: {}", *sale_transaction_id)))
-            ),
-            _ => HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Unable to retrieve transaction".to_string(), None, Some(err.to_string()))
-            )
-        },
-        Ok(transaction) => {
-            if transaction.user_id == user_session.user_data.user_id {
-                HttpResponse::Ok().json(
-                   ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
-                )
-            } else {
-                HttpResponse::Forbidden().json(
-                    ApiResponse::<()>::new(false, "Content restricted".to_string(), None, Some("User does not match transaction owner".to_string()))
-                )
-            }
-        }
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No transaction matching id: {}", *sale_transaction_id))?;
+
+    if transaction.user_id != user_session.user_data.user_id {
+        return Err(AppError::Forbidden("User does not match transaction owner".to_string()));
     }
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
+    ))
 }
 
 #[get("/api/my-sale-transaction")]
-async fn get_my_sale_transaction(app_state: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
-
-    let result = sqlx::query_as!(
-        SaleTransactionObject,
-        "SELECT 
+async fn get_my_sale_transaction(app_state: web::Data<AppState>, user_session: AuthSession, page: Pagination<SaleTransactionObject>) -> Result<HttpResponse, AppError> {
+    let user_id = user_session.user_data.user_id;
+    let transaction = page.fetch(&app_state.db_pool, |query| {
+        query.push("SELECT 
         st.sale_transaction_id,
         st.user_id,
         st.down_payment,
@@ -346,6 +433,7 @@ async fn get_my_sale_transaction(app_state: web::Data<AppState>, req: HttpReques
 		st.monthly_mortgage,
         st.sale_date,
         st.status,
+        st.payment_deadline,
         JSON_BUILD_OBJECT(
             'sale_property_id', sp.sale_property_id,
             'title', sp.title,
@@ -362,83 +450,241 @@ async fn get_my_sale_transaction(app_state: web::Data<AppState>, req: HttpReques
         ) AS sale_property
         FROM sale_transaction st
         JOIN sale_property sp ON st.sale_property_id = sp.sale_property_id
-        WHERE user_id = $1",
-        user_session.user_data.user_id,
-    ).fetch_all(&app_state.db_pool).await;
-
-    match result {
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
-                ApiResponse::<()>::new(false, "Transaction not found".to_string(), None, Some(format!("Error: No transaction found for user_id: {}", user_session.user_data.user_id)))
-            ),
-            _ => HttpResponse::InternalServerError().json(
-                ApiResponse::<()>::new(false, "Unable to retrieve transaction".to_string(), None, Some(err.to_string()))
-            )
-        },
-        Ok(transaction) => HttpResponse::Ok().json(
-                   ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
-                )
+        WHERE user_id = ").push_bind(user_id);
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully retrieved transaction".to_string(), Some(transaction), None)
+    ))
+}
+
+/// Locks a sale transaction for the rest of `conn`'s transaction, provided
+/// it belongs to `user_id`.
+async fn lock_own_sale_transaction(conn: &mut PgConnection, sale_transaction_id: Uuid, user_id: Uuid) -> Result<SaleTransaction, AppError> {
+    let sale_transaction = sqlx::query_as!(
+        SaleTransaction,
+        r#"SELECT sale_transaction_id, sale_property_id, user_id, down_payment, installment_duration, monthly_mortgage, sale_date, status AS "status: TransactionStatus", payment_deadline, down_payment_paid_at, cancelled_at, refund_amount
+        FROM sale_transaction WHERE sale_transaction_id = $1 FOR UPDATE"#,
+        sale_transaction_id
+    ).fetch_one(conn).await.or_not_found("Invalid transaction id")?;
+
+    if sale_transaction.user_id != user_id {
+        return Err(AppError::Forbidden("User not authorized".to_string()));
     }
+
+    Ok(sale_transaction)
 }
 
+/// Locks a sale transaction and returns its status.
+async fn lock_sale_status(conn: &mut PgConnection, sale_transaction_id: Uuid) -> sqlx::Result<Option<TransactionStatus>> {
+    sqlx::query_scalar!(
+        r#"SELECT status AS "status: TransactionStatus" FROM sale_transaction WHERE sale_transaction_id = $1 FOR UPDATE"#,
+        sale_transaction_id
+    ).fetch_optional(conn).await
+}
+
+/// Moves a sale from `current` to `Installment`, or to `Paid` once nothing
+/// is left to pay.
+async fn settle_sale_status(conn: &mut PgConnection, sale_transaction_id: Uuid, current: TransactionStatus) -> sqlx::Result<()> {
+    let next = if count_unpaid(conn, sale_transaction_id).await? == 0 { TransactionStatus::Paid } else { TransactionStatus::Installment };
+
+    if next != current && current.can_become(TransactionKind::Sale, next) {
+        sqlx::query!(
+            "UPDATE sale_transaction SET status = $2 WHERE sale_transaction_id = $1",
+            sale_transaction_id,
+            next as TransactionStatus
+        ).execute(conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Opens a payment for the down payment. Once the provider confirms it the
+/// sale moves to `Installment`, and to `Paid` after every installment.
 #[post("/api/pay-sale/{sale_transaction_id}")]
-async fn pay_sale(app_state: web::Data<AppState>, req: HttpRequest, sale_transaction_id: web::Path<Uuid>) -> impl Responder {
-    let user_session = match get_session(app_state.clone(), &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
+async fn pay_sale(app_state: web::Data<AppState>, user_session: AuthSession, sale_transaction_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let mut trx = app_state.db_pool.begin().await?;
 
-    let mut trx = match app_state.db_pool.begin().await {
-        Err(err) => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Internal Server Error".to_string(), None, Some(err.to_string()))
-        ),
+    let sale_transaction = lock_own_sale_transaction(&mut trx, *sale_transaction_id, user_session.user_data.user_id).await?;
 
-        Ok(tr) => tr
-    };
+    if sale_transaction.status != TransactionStatus::Unpaid {
+        return Err(AppError::Conflict(format!("Error: Down payment cannot be paid while the transaction is {}", sale_transaction.status)));
+    }
+
+    check_payment_deadline(sale_transaction.payment_deadline).map_err(AppError::Conflict)?;
+
+    let payment = start_payment(app_state.payment_provider.as_ref(), &mut trx, sale_transaction.user_id, PaymentTarget::SaleDownPayment(sale_transaction.sale_transaction_id), sale_transaction.down_payment).await?;
+
+    trx.commit().await?;
+
+    Ok(HttpResponse::Accepted().json(
+        ApiResponse::new(true, "Payment pending".to_string(), Some(payment), None)
+    ))
+}
+
+/// Records the down payment of a sale after its payment succeeded. Returns
+/// `false` if the sale was no longer waiting for it.
+pub async fn confirm_down_payment(conn: &mut PgConnection, sale_transaction_id: Uuid) -> sqlx::Result<bool> {
+    if lock_sale_status(conn, sale_transaction_id).await? != Some(TransactionStatus::Unpaid) {
+        return Ok(false);
+    }
 
-    let result = sqlx::query_as!(
+    sqlx::query!(
+        "UPDATE sale_transaction SET down_payment_paid_at = NOW() WHERE sale_transaction_id = $1",
+        sale_transaction_id
+    ).execute(&mut *conn).await?;
+
+    settle_sale_status(conn, sale_transaction_id, TransactionStatus::Unpaid).await?;
+
+    Ok(true)
+}
+
+/// Records installment `n` of a sale after its payment succeeded. Returns
+/// `false` if it was no longer waiting for payment.
+pub async fn confirm_installment_payment(conn: &mut PgConnection, sale_transaction_id: Uuid, n: i32) -> sqlx::Result<bool> {
+    if lock_sale_status(conn, sale_transaction_id).await? != Some(TransactionStatus::Installment) {
+        return Ok(false);
+    }
+
+    let result = sqlx::query!(
+        "UPDATE sale_installment SET status = 'Paid', paid_at = NOW()
+        WHERE sale_transaction_id = $1 AND installment_number = $2 AND status = 'Unpaid'",
+        sale_transaction_id,
+        n
+    ).execute(&mut *conn).await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    settle_sale_status(conn, sale_transaction_id, TransactionStatus::Installment).await?;
+
+    Ok(true)
+}
+
+#[get("/api/sale-transaction/{sale_transaction_id}/installments")]
+async fn get_sale_installments(app_state: web::Data<AppState>, session: AuthSession, sale_transaction_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.db_pool.acquire().await?;
+
+    let sale_transaction = sqlx::query_as!(
         SaleTransaction,
-        "UPDATE sale_transaction SET status = 'Paid' WHERE sale_transaction_id = $1 returning *",
+        r#"SELECT sale_transaction_id, sale_property_id, user_id, down_payment, installment_duration, monthly_mortgage, sale_date, status AS "status: TransactionStatus", payment_deadline, down_payment_paid_at, cancelled_at, refund_amount
+        FROM sale_transaction WHERE sale_transaction_id = $1"#,
         *sale_transaction_id
-    ).fetch_one(&mut *trx).await;
-
-    let sale_transaction = match result {
-        Err(err) => match err {
-        sqlx::Error::RowNotFound => return  HttpResponse::NotFound().json(
-            ApiResponse::<()>::new(false, "Failed processing payment".to_string(), None, Some("Invalid transaction id".to_string()))
-        ),
-        _ => return  HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Failed processing payment".to_string(), None, Some(err.to_string()))
-        )
-        },
-        Ok(trans) => trans,
-    };
+    ).fetch_one(&mut *conn).await.or_not_found(format!("Error: No transaction matching id: {}", *sale_transaction_id))?;
 
-    if sale_transaction.user_id != user_session.user_data.user_id {
-        trx.rollback().await.unwrap();
+    if sale_transaction.user_id != session.user_data.user_id {
+        return Err(AppError::Forbidden("User does not match transaction owner".to_string()));
+    }
 
-        return HttpResponse::Forbidden().json(
-            ApiResponse::<()>::new(false, "Failed processing payment".to_string(), None, Some("User not authorized".to_string()))
-        );
+    let installments = get_ledger(&mut conn, *sale_transaction_id).await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully retrieved installments".to_string(), Some(InstallmentLedger::new(
+            sale_transaction.sale_transaction_id,
+            sale_transaction.status,
+            sale_transaction.down_payment,
+            sale_transaction.down_payment_paid_at,
+            installments
+        )), None)
+    ))
+}
+
+/// Opens a payment for installment `n` of a sale. Installments are paid in
+/// order, after the down payment.
+#[post("/api/sale-transaction/{sale_transaction_id}/installments/{n}/pay")]
+async fn pay_sale_installment(app_state: web::Data<AppState>, session: AuthSession, path: web::Path<(Uuid, i32)>) -> Result<HttpResponse, AppError> {
+    let (sale_transaction_id, n) = path.into_inner();
+
+    let mut trx = app_state.db_pool.begin().await?;
+
+    let sale_transaction = lock_own_sale_transaction(&mut trx, sale_transaction_id, session.user_data.user_id).await?;
+
+    match sale_transaction.status {
+        TransactionStatus::Installment => (),
+        TransactionStatus::Unpaid => return Err(AppError::Conflict("Error: The down payment must be paid first".to_string())),
+        status => return Err(AppError::Conflict(format!("Error: Installments cannot be paid while the transaction is {}", status))),
     }
 
-    trx.commit().await.unwrap();
+    if n < 1 || n > sale_transaction.installment_duration {
+        return Err(AppError::NotFound(format!("Error: No installment {} on this transaction", n)));
+    }
 
-    HttpResponse::Ok().json(
-        ApiResponse::new(true, "Payment processing success".to_string(), Some(sale_transaction), None)
-    )
+    let first_unpaid = sqlx::query!(
+        "SELECT installment_number, amount FROM sale_installment WHERE sale_transaction_id = $1 AND status = 'Unpaid'
+        ORDER BY installment_number LIMIT 1",
+        sale_transaction_id
+    ).fetch_optional(&mut *trx).await?.map(|row| (row.installment_number, row.amount));
+
+    let amount = match first_unpaid {
+        Some((first_unpaid, amount)) if first_unpaid == n => amount,
+        Some((first_unpaid, _)) if first_unpaid < n => return Err(AppError::Conflict(format!("Error: Installment {} must be paid first", first_unpaid))),
+        _ => return Err(AppError::Conflict(format!("Error: Installment {} is already paid", n))),
+    };
+
+    let payment = start_payment(app_state.payment_provider.as_ref(), &mut trx, sale_transaction.user_id, PaymentTarget::SaleInstallment(sale_transaction_id, n), amount).await?;
+
+    trx.commit().await?;
+
+    Ok(HttpResponse::Accepted().json(
+        ApiResponse::new(true, "Payment pending".to_string(), Some(payment), None)
+    ))
 }
 
+/// Cancels a sale that isn't fully paid. What was paid towards it is
+/// refunded less the cancellation fee, and its unpaid installments are
+/// cancelled. A payment still pending is refunded in full if it later
+/// succeeds.
+#[post("/api/sale-transaction/{sale_transaction_id}/cancel")]
+async fn cancel_sale_transaction(app_state: web::Data<AppState>, session: AuthSession, sale_transaction_id: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+    let mut trx = app_state.db_pool.begin().await?;
+
+    let sale_transaction = lock_own_sale_transaction(&mut trx, *sale_transaction_id, session.user_data.user_id).await?;
+
+    sale_transaction.status.transition(TransactionKind::Sale, TransactionStatus::Cancelled).map_err(AppError::Conflict)?;
+
+    let installments_paid = sqlx::query_scalar!(
+        r#"SELECT COALESCE(SUM(amount), 0)::BIGINT AS "paid!" FROM sale_installment WHERE sale_transaction_id = $1 AND status = 'Paid'"#,
+        *sale_transaction_id
+    ).fetch_one(&mut *trx).await?;
+
+    let down_payment_paid = if sale_transaction.down_payment_paid_at.is_some() { sale_transaction.down_payment } else { 0 };
+    let refund_amount = app_state.cancellation_policy.sale_refund(down_payment_paid + installments_paid);
+
+    sqlx::query!(
+        "UPDATE sale_installment SET status = 'Cancelled' WHERE sale_transaction_id = $1 AND status = 'Unpaid'",
+        *sale_transaction_id
+    ).execute(&mut *trx).await?;
+
+    let sale_transaction = sqlx::query_as!(
+        SaleTransaction,
+        r#"UPDATE sale_transaction SET status = 'Cancelled', cancelled_at = NOW(), refund_amount = $2
+        WHERE sale_transaction_id = $1
+        RETURNING sale_transaction_id, sale_property_id, user_id, down_payment, installment_duration, monthly_mortgage, sale_date, status AS "status: TransactionStatus", payment_deadline, down_payment_paid_at, cancelled_at, refund_amount"#,
+        *sale_transaction_id,
+        refund_amount
+    ).fetch_one(&mut *trx).await?;
+
+    trx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Transaction cancelled".to_string(), Some(sale_transaction), None)
+    ))
+}
 
 pub fn init_routes(cfg: &mut ServiceConfig) {
     cfg
         .service(add_sale_property)
         .service(get_sale_properties)
         .service(get_sale_property_by_id)
+        .service(update_sale_property)
+        .service(delete_sale_property)
         .service(post_sale_transaction)
+        .service(post_mortgage_simulation)
         .service(get_sale_transaction_by_id)
         .service(get_my_sale_transaction)
-        .service(pay_sale);
+        .service(pay_sale)
+        .service(get_sale_installments)
+        .service(pay_sale_installment)
+        .service(cancel_sale_transaction);
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+use chrono::{Months, NaiveDate};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// Mortgage terms offered on sale properties.
+#[derive(Debug, Clone, Copy)]
+pub struct MortgagePolicy {
+    /// Annual rate charged during the fixed period.
+    pub fixed_rate: f64,
+    /// Years the fixed rate lasts before `floating_rate` applies. `None`
+    /// keeps the fixed rate for the whole loan.
+    pub fixed_years: Option<i32>,
+    pub floating_rate: f64,
+    /// Smallest share of the price that must be paid up front.
+    pub min_down_payment_ratio: f64,
+    pub min_months: i32,
+    pub max_months: i32,
+}
+
+impl MortgagePolicy {
+    pub fn from_config(config: &Config) -> Self {
+        MortgagePolicy {
+            fixed_rate: config.mortgage_fixed_rate,
+            fixed_years: config.mortgage_fixed_years,
+            floating_rate: config.mortgage_floating_rate.unwrap_or(config.mortgage_fixed_rate),
+            min_down_payment_ratio: config.mortgage_min_down_payment_ratio,
+            min_months: config.mortgage_min_months,
+            max_months: config.mortgage_max_months,
+        }
+    }
+
+    /// Annual rate charged in `month`, counted from 1.
+    fn rate_for(&self, month: i32) -> f64 {
+        match self.fixed_years {
+            Some(years) if month > years * 12 => self.floating_rate,
+            _ => self.fixed_rate,
+        }
+    }
+}
+
+/// One month of an amortization schedule.
+#[derive(Debug, Serialize, Clone)]
+pub struct Installment {
+    pub month: i32,
+    pub due_date: NaiveDate,
+    pub annual_rate: f64,
+    pub payment: i64,
+    pub principal: i64,
+    pub interest: i64,
+    /// Loan left after this payment.
+    pub balance: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MortgagePlan {
+    pub property_price: i64,
+    pub down_payment: i64,
+    pub loan_amount: i64,
+    pub installment_duration: i32,
+    /// First month's payment. Payments change once a floating rate applies.
+    pub monthly_mortgage: i64,
+    pub total_interest: i64,
+    /// Down payment plus every installment.
+    pub total_payment: i64,
+    pub schedule: Vec<Installment>,
+}
+
+/// Level monthly payment repaying `balance` over `months` at `annual_rate`.
+fn level_payment(balance: f64, annual_rate: f64, months: i32) -> f64 {
+    let monthly_rate = annual_rate / 12.0;
+
+    if monthly_rate == 0.0 {
+        return balance / months as f64;
+    }
+
+    let growth = (1.0 + monthly_rate).powi(months);
+    balance * monthly_rate * growth / (growth - 1.0)
+}
+
+/// Validates a purchase and builds its amortization schedule, with the
+/// first installment due a month after `start_date`.
+///
+/// Payments are level while the rate stays the same. When the floating rate
+/// starts, the remaining balance is spread again over the remaining months.
+/// The last installment settles whatever rounding left over.
+pub fn plan_mortgage(policy: &MortgagePolicy, property_price: i64, down_payment: i64, installment_duration: i32, start_date: NaiveDate) -> Result<MortgagePlan, String> {
+    if property_price <= 0 {
+        return Err("Error: property_price must be positive".to_string());
+    }
+
+    if down_payment < 0 || down_payment >= property_price {
+        return Err("Error: down_payment must be between 0 and property_price".to_string());
+    }
+
+    if (down_payment as f64) < property_price as f64 * policy.min_down_payment_ratio {
+        return Err(format!("Error: down_payment must be at least {}% of property_price", policy.min_down_payment_ratio * 100.0));
+    }
+
+    if installment_duration < policy.min_months.max(1) || installment_duration > policy.max_months {
+        return Err(format!("Error: installment_duration must be between {} and {} months", policy.min_months, policy.max_months));
+    }
+
+    let loan_amount = property_price - down_payment;
+    let mut balance = loan_amount;
+    let mut payment = 0;
+    let mut schedule = Vec::with_capacity(installment_duration as usize);
+
+    for month in 1..=installment_duration {
+        let annual_rate = policy.rate_for(month);
+        if month == 1 || annual_rate != policy.rate_for(month - 1) {
+            payment = level_payment(balance as f64, annual_rate, installment_duration - month + 1).round() as i64;
+        }
+
+        let interest = (balance as f64 * annual_rate / 12.0).round() as i64;
+        let principal = if month == installment_duration { balance } else { (payment - interest).clamp(0, balance) };
+        balance -= principal;
+
+        schedule.push(Installment {
+            month,
+            due_date: start_date.checked_add_months(Months::new(month as u32)).ok_or("Error: Loan ends too far in the future".to_string())?,
+            annual_rate,
+            payment: principal + interest,
+            principal,
+            interest,
+            balance,
+        });
+    }
+
+    let total_interest = schedule.iter().map(|installment| installment.interest).sum();
+
+    Ok(MortgagePlan {
+        property_price,
+        down_payment,
+        loan_amount,
+        installment_duration,
+        monthly_mortgage: schedule[0].payment,
+        total_interest,
+        total_payment: property_price + total_interest,
+        schedule,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> MortgagePolicy {
+        MortgagePolicy {
+            fixed_rate: 0.06,
+            fixed_years: None,
+            floating_rate: 0.06,
+            min_down_payment_ratio: 0.1,
+            min_months: 12,
+            max_months: 360,
+        }
+    }
+
+    fn start() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 1, 31).unwrap()
+    }
+
+    #[test]
+    fn zero_rate_splits_the_loan_evenly() {
+        let policy = MortgagePolicy { fixed_rate: 0.0, floating_rate: 0.0, ..policy() };
+        let plan = plan_mortgage(&policy, 1_300_000, 130_000, 12, start()).unwrap();
+
+        assert_eq!(plan.loan_amount, 1_170_000);
+        assert_eq!(plan.total_interest, 0);
+        assert_eq!(plan.total_payment, 1_300_000);
+        assert!(plan.schedule.iter().all(|installment| installment.payment == 97_500 && installment.interest == 0));
+    }
+
+    #[test]
+    fn last_installment_clears_the_balance() {
+        let plan = plan_mortgage(&policy(), 1_000_003, 100_001, 37, start()).unwrap();
+        let last = plan.schedule.last().unwrap();
+
+        assert_eq!(plan.schedule.len(), 37);
+        assert_eq!(last.balance, 0);
+        assert_eq!(plan.schedule.iter().map(|installment| installment.principal).sum::<i64>(), plan.loan_amount);
+        assert_eq!(plan.total_payment, plan.property_price + plan.total_interest);
+    }
+
+    #[test]
+    fn due_dates_follow_the_start_date() {
+        let plan = plan_mortgage(&policy(), 1_000_000, 100_000, 12, start()).unwrap();
+
+        assert_eq!(plan.schedule[0].due_date, NaiveDate::from_ymd_opt(2025, 2, 28).unwrap());
+        assert_eq!(plan.schedule[1].due_date, NaiveDate::from_ymd_opt(2025, 3, 31).unwrap());
+    }
+
+    #[test]
+    fn floating_rate_applies_after_the_fixed_period() {
+        let policy = MortgagePolicy { fixed_years: Some(1), floating_rate: 0.12, ..policy() };
+        let plan = plan_mortgage(&policy, 1_000_000, 100_000, 24, start()).unwrap();
+        let (fixed, floating) = plan.schedule.split_at(12);
+
+        assert!(fixed.iter().all(|installment| installment.annual_rate == 0.06));
+        assert!(floating.iter().all(|installment| installment.annual_rate == 0.12));
+        assert!(floating[0].payment > fixed[11].payment);
+        assert_eq!(floating[0].interest, (fixed[11].balance as f64 * 0.01).round() as i64);
+        assert_eq!(plan.schedule.last().unwrap().balance, 0);
+        assert_eq!(plan.monthly_mortgage, fixed[0].payment);
+    }
+
+    #[test]
+    fn down_payment_must_stay_within_bounds() {
+        assert!(plan_mortgage(&policy(), 1_000_000, 99_999, 12, start()).is_err());
+        assert!(plan_mortgage(&policy(), 1_000_000, 100_000, 12, start()).is_ok());
+        assert!(plan_mortgage(&policy(), 1_000_000, 1_000_000, 12, start()).is_err());
+        assert!(plan_mortgage(&policy(), 1_000_000, -1, 12, start()).is_err());
+    }
+
+    #[test]
+    fn duration_must_stay_within_bounds() {
+        assert!(plan_mortgage(&policy(), 1_000_000, 100_000, 11, start()).is_err());
+        assert!(plan_mortgage(&policy(), 1_000_000, 100_000, 361, start()).is_err());
+        assert!(plan_mortgage(&policy(), 1_000_000, 100_000, 360, start()).is_ok());
+    }
+}
@@ -0,0 +1,326 @@
+use std::{fs, io::ErrorKind, path::{Component, Path, PathBuf}, sync::Arc};
+
+use actix_web::{get, http::header, web::{self, ServiceConfig}, HttpResponse};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use object_store::{aws::{AmazonS3, AmazonS3Builder}, path::Path as ObjectPath, ObjectStore, PutPayload};
+use uuid::Uuid;
+
+use crate::{config::Config, utils::error::AppError, AppState};
+
+pub mod reconcile;
+
+/// Where uploaded files live. Keys are `/`-separated relative paths such as
+/// `rents/<file name>`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, contents: Vec<u8>) -> Result<(), String>;
+
+    /// Returns `None` when nothing is stored under `key`.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String>;
+
+    async fn exists(&self, key: &str) -> Result<bool, String>;
+
+    /// Deleting a missing key is not an error.
+    async fn delete(&self, key: &str) -> Result<(), String>;
+
+    /// Files directly under `prefix`, such as `rents`.
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String>;
+
+    /// URL clients use to download `key`.
+    fn public_url(&self, key: &str) -> String;
+
+    /// Checks the backend is reachable without writing anything, for
+    /// readiness probes.
+    async fn probe(&self) -> Result<(), String>;
+}
+
+/// A file as listed by `Storage::list`.
+pub struct StoredObject {
+    pub key: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+/// Keys are relative paths without `.` or `..` segments. Backslashes are
+/// refused too, since some platforms and S3 clients treat them as separators.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains('\\') && Path::new(key).components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Builds the storage selected by `config.storage_backend`. Local storage
+/// keeps files in `config.upload_dir`.
+pub fn from_config(config: &Config) -> Result<Arc<dyn Storage>, String> {
+    // Files are served by `get_uploaded_file` unless a bucket is public.
+    let proxy_url = format!("{}/uploaded", config.host_url.trim_end_matches('/'));
+
+    match config.storage_backend.as_str() {
+        "s3" => {
+            let required = |value: &Option<String>, name: &str| value.clone().ok_or(format!("Please provide {}", name));
+
+            let mut builder = AmazonS3Builder::new()
+                .with_bucket_name(required(&config.s3_bucket, "s3_bucket")?)
+                .with_region(&config.s3_region)
+                .with_access_key_id(required(&config.s3_access_key_id, "s3_access_key_id")?)
+                .with_secret_access_key(config.s3_secret_access_key.as_ref().ok_or("Please provide s3_secret_access_key")?.expose());
+
+            // Custom endpoints are S3-compatible servers such as MinIO.
+            if let Some(endpoint) = &config.s3_endpoint {
+                builder = builder.with_allow_http(endpoint.starts_with("http://")).with_endpoint(endpoint);
+            }
+
+            let public_url = config.s3_public_url.clone().unwrap_or(proxy_url);
+
+            Ok(Arc::new(S3Storage::new(builder.build().map_err(|err| err.to_string())?, public_url)))
+        },
+        "local" => Ok(Arc::new(LocalStorage::new(&config.upload_dir, proxy_url)?)),
+        other => Err(format!("Unknown storage backend: {}", other)),
+    }
+}
+
+/// Stores files in a directory on this machine.
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl LocalStorage {
+    /// Creates `root` if it doesn't exist yet.
+    pub fn new(root: impl Into<PathBuf>, public_url: String) -> Result<Self, String> {
+        let root = root.into();
+        fs::create_dir_all(&root).map_err(|err| format!("Unable to create {}: {}", root.display(), err))?;
+
+        Ok(LocalStorage { root, public_url })
+    }
+
+    /// Resolves `key` inside the root, refusing anything that could escape it.
+    fn path_of(&self, key: &str) -> Result<PathBuf, String> {
+        if !is_valid_key(key) {
+            return Err(format!("Invalid storage key: {}", key));
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, contents: Vec<u8>) -> Result<(), String> {
+        let destination = self.path_of(key)?;
+
+        web::block(move || {
+            if let Some(parent_dir) = destination.parent() {
+                fs::create_dir_all(parent_dir)?;
+            }
+
+            // Write next to the destination first so readers never see a
+            // half-written file.
+            let partial = destination.with_file_name(format!(".{}.partial", Uuid::new_v4()));
+            fs::write(&partial, contents)?;
+            fs::rename(&partial, &destination)
+        }).await.map_err(|err| err.to_string())?.map_err(|err| err.to_string())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path_of(key)?;
+
+        match web::block(move || fs::read(path)).await.map_err(|err| err.to_string())? {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        let path = self.path_of(key)?;
+
+        web::block(move || path.is_file()).await.map_err(|err| err.to_string())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let path = self.path_of(key)?;
+
+        match web::block(move || fs::remove_file(path)).await.map_err(|err| err.to_string())? {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String> {
+        let dir = self.path_of(prefix)?;
+        let prefix = prefix.to_string();
+
+        web::block(move || {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(err) => return Err(err),
+            };
+
+            let mut objects = Vec::new();
+
+            for entry in entries {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+
+                if metadata.is_file() {
+                    objects.push(StoredObject {
+                        key: format!("{}/{}", prefix, entry.file_name().to_string_lossy()),
+                        last_modified: metadata.modified()?.into(),
+                    });
+                }
+            }
+
+            Ok(objects)
+        }).await.map_err(|err| err.to_string())?.map_err(|err| err.to_string())
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    async fn probe(&self) -> Result<(), String> {
+        let root = self.root.clone();
+
+        let metadata = web::block(move || fs::metadata(root)).await.map_err(|err| err.to_string())?
+            .map_err(|err| format!("Unable to read {}: {}", self.root.display(), err))?;
+
+        if !metadata.is_dir() {
+            return Err(format!("{} is not a directory", self.root.display()));
+        }
+
+        if metadata.permissions().readonly() {
+            return Err(format!("{} is read-only", self.root.display()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Stores files in an S3 bucket or any S3-compatible service.
+pub struct S3Storage {
+    client: AmazonS3,
+    public_url: String,
+}
+
+impl S3Storage {
+    pub fn new(client: AmazonS3, public_url: String) -> Self {
+        S3Storage { client, public_url }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, contents: Vec<u8>) -> Result<(), String> {
+        self.client.put(&ObjectPath::from(key), PutPayload::from(contents)).await.map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match self.client.get(&ObjectPath::from(key)).await {
+            Ok(result) => Ok(Some(result.bytes().await.map_err(|err| err.to_string())?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        match self.client.head(&ObjectPath::from(key)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        match self.client.delete(&ObjectPath::from(key)).await {
+            Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String> {
+        let listed = self.client.list_with_delimiter(Some(&ObjectPath::from(prefix))).await.map_err(|err| err.to_string())?;
+
+        Ok(listed.objects.into_iter()
+            .map(|object| StoredObject { key: object.location.to_string(), last_modified: object.last_modified })
+            .collect())
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    /// Looks up a key that is never written. Not finding it shows the bucket
+    /// answers and accepts the credentials.
+    async fn probe(&self) -> Result<(), String> {
+        match self.client.head(&ObjectPath::from(".readyz")).await {
+            Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+fn content_type(key: &str) -> &'static str {
+    match key.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+#[get("/uploaded/{key:.*}")]
+async fn get_uploaded_file(app_state: web::Data<AppState>, key: web::Path<String>) -> Result<HttpResponse, AppError> {
+    let stored = if is_valid_key(&key) { app_state.storage.get(&key).await.map_err(AppError::Internal)? } else { None };
+
+    let contents = stored.ok_or_else(|| AppError::NotFound(format!("Error: No file matching key: {}", *key)))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type(&key))
+        // Stored names are content hashes, so a key never changes content.
+        .insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+        .body(contents))
+}
+
+pub fn init_routes(cfg: &mut ServiceConfig) {
+    cfg
+        .service(get_uploaded_file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_keys_are_valid() {
+        assert!(is_valid_key("rents/3f1a9c.webp"));
+        assert!(is_valid_key("sales/3f1a9c-thumb.jpg"));
+        assert!(is_valid_key("rents"));
+    }
+
+    #[test]
+    fn keys_escaping_the_root_are_refused() {
+        assert!(!is_valid_key("../x"));
+        assert!(!is_valid_key("a/../../b"));
+        assert!(!is_valid_key("rents/.."));
+        assert!(!is_valid_key("./rents/x.jpg"));
+        assert!(!is_valid_key("/abs"));
+        assert!(!is_valid_key("/etc/passwd"));
+    }
+
+    #[test]
+    fn empty_keys_and_backslashes_are_refused() {
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("rents\\x.jpg"));
+        assert!(!is_valid_key("..\\x"));
+    }
+
+    #[test]
+    fn local_storage_refuses_invalid_keys() {
+        let storage = LocalStorage { root: PathBuf::from("/nonexistent"), public_url: String::new() };
+
+        assert!(storage.path_of("../x").is_err());
+        assert_eq!(storage.path_of("rents/x.webp"), Ok(PathBuf::from("/nonexistent/rents/x.webp")));
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::property_picture::{processing::all_variant_file_names, PropertyKind};
+
+use super::Storage;
+
+/// Files younger than this are left alone: they may belong to an upload
+/// whose pictures are still being recorded.
+const MIN_ORPHAN_AGE_MINUTES: i64 = 60;
+
+/// Storage keys of every file some picture row still refers to, including
+/// pictures of deleted listings.
+async fn referenced_keys(db_pool: &PgPool) -> sqlx::Result<HashSet<String>> {
+    let rows = sqlx::query!(
+        "SELECT file_name, rent_property_id IS NOT NULL AS \"is_rent!\" FROM property_picture"
+    ).fetch_all(db_pool).await?;
+
+    Ok(rows.into_iter()
+        .flat_map(|row| {
+            let kind = if row.is_rent { PropertyKind::Rent } else { PropertyKind::Sale };
+
+            all_variant_file_names(&row.file_name).into_iter().map(move |name| kind.storage_key(&name))
+        })
+        .collect())
+}
+
+/// Stored picture files no picture row refers to, such as those left by an
+/// upload interrupted before its pictures were recorded.
+pub async fn find_orphans(db_pool: &PgPool, storage: &dyn Storage) -> Result<Vec<String>, String> {
+    let referenced = referenced_keys(db_pool).await.map_err(|err| err.to_string())?;
+    let cutoff = Utc::now() - Duration::minutes(MIN_ORPHAN_AGE_MINUTES);
+
+    let mut orphans = Vec::new();
+
+    for kind in [PropertyKind::Rent, PropertyKind::Sale] {
+        for object in storage.list(kind.storage_dir()).await? {
+            if object.last_modified < cutoff && !referenced.contains(&object.key) {
+                orphans.push(object.key);
+            }
+        }
+    }
+
+    orphans.sort();
+
+    Ok(orphans)
+}
+
+/// The `reconcile-uploads` command. Lists orphaned files and, with `delete`,
+/// removes them.
+pub async fn run(db_pool: &PgPool, storage: &dyn Storage, delete: bool) -> Result<(), String> {
+    let orphans = find_orphans(db_pool, storage).await?;
+    let mut deleted = 0;
+
+    for key in &orphans {
+        info!(key, "Orphaned upload");
+
+        if delete {
+            match storage.delete(key).await {
+                Ok(()) => deleted += 1,
+                Err(err) => warn!(key, error = %err, "Failed removing orphaned upload"),
+            }
+        }
+    }
+
+    info!(orphans = orphans.len(), deleted, "Upload reconciliation finished");
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use std::{fmt, time::Duration as StdDuration};
+
+use actix_web::rt;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::config::Config;
+
+/// Status of a rent or sale transaction, stored as the `transaction_status`
+/// Postgres enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "transaction_status")]
+pub enum TransactionStatus {
+    Unpaid,
+    /// Sales only: the down payment is in, installments are still due.
+    Installment,
+    Paid,
+    Cancelled,
+}
+
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionStatus::Unpaid => "Unpaid",
+            TransactionStatus::Installment => "Installment",
+            TransactionStatus::Paid => "Paid",
+            TransactionStatus::Cancelled => "Cancelled",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Rent,
+    Sale,
+}
+
+impl TransactionStatus {
+    /// Whether a `kind` transaction may move from this status to `next`.
+    ///
+    /// Rents are paid in one go and can be cancelled until they start, even
+    /// once paid. Sales pay their down payment, then their installments, and
+    /// can no longer be cancelled once fully paid.
+    pub fn can_become(self, kind: TransactionKind, next: TransactionStatus) -> bool {
+        use TransactionStatus::*;
+
+        match kind {
+            TransactionKind::Rent => matches!((self, next), (Unpaid, Paid) | (Unpaid, Cancelled) | (Paid, Cancelled)),
+            TransactionKind::Sale => matches!(
+                (self, next),
+                (Unpaid, Installment) | (Unpaid, Paid) | (Installment, Paid) | (Unpaid, Cancelled) | (Installment, Cancelled)
+            ),
+        }
+    }
+
+    pub fn transition(self, kind: TransactionKind, next: TransactionStatus) -> Result<TransactionStatus, String> {
+        if self.can_become(kind, next) {
+            Ok(next)
+        } else {
+            Err(format!("Error: Transaction cannot go from {} to {}", self, next))
+        }
+    }
+}
+
+/// How much of what was paid is refunded when a transaction is cancelled.
+#[derive(Debug, Clone, Copy)]
+pub struct CancellationPolicy {
+    /// Paid rent cancelled at least this many days before it starts is
+    /// refunded in full.
+    pub rent_full_refund_days: i64,
+    /// Share of paid rent refunded when cancelled closer to its start.
+    pub rent_late_refund_ratio: f64,
+    /// Share of what was paid towards a sale that is kept on cancellation.
+    pub sale_cancellation_fee_ratio: f64,
+}
+
+impl CancellationPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        CancellationPolicy {
+            rent_full_refund_days: config.rent_full_refund_days,
+            rent_late_refund_ratio: config.rent_late_refund_ratio,
+            sale_cancellation_fee_ratio: config.sale_cancellation_fee_ratio,
+        }
+    }
+
+    /// Refund for paid rent starting on `start_date`. Rent that has started
+    /// can't be cancelled.
+    pub fn rent_refund(&self, paid: i64, start_date: NaiveDate, today: NaiveDate) -> Result<i64, String> {
+        if today >= start_date {
+            return Err("Error: Rent that has already started cannot be cancelled".to_string());
+        }
+
+        if (start_date - today).num_days() >= self.rent_full_refund_days {
+            Ok(paid)
+        } else {
+            Ok((paid as f64 * self.rent_late_refund_ratio).round() as i64)
+        }
+    }
+
+    /// Refund of the down payment and installments paid towards a sale.
+    pub fn sale_refund(&self, paid: i64) -> i64 {
+        paid - (paid as f64 * self.sale_cancellation_fee_ratio).round() as i64
+    }
+}
+
+/// How long a new transaction holds its property while waiting for payment.
+#[derive(Debug, Clone, Copy)]
+pub struct ReservationPolicy {
+    pub payment_window: Duration,
+}
+
+impl ReservationPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        ReservationPolicy { payment_window: Duration::hours(config.payment_window_hours) }
+    }
+}
+
+/// Refuses payments started after `payment_deadline`, even if the expiry job
+/// hasn't cancelled the transaction yet.
+pub fn check_payment_deadline(payment_deadline: Option<DateTime<Utc>>) -> Result<(), String> {
+    match payment_deadline {
+        Some(deadline) if deadline <= Utc::now() => Err("Error: The payment deadline has passed".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Cancels every unpaid transaction past its payment deadline, releasing its
+/// property. A payment still pending is left as is: if it succeeds later,
+/// `settle_payment` records it as owed back in full. Returns how many rent
+/// and sale transactions were cancelled.
+pub async fn expire_unpaid_transactions(db_pool: &PgPool) -> sqlx::Result<(u64, u64)> {
+    // Unpaid -> Cancelled is allowed for both kinds, and nothing was paid
+    // so nothing is refunded.
+    let rents = sqlx::query!(
+        "UPDATE rent_transaction SET status = 'Cancelled', cancelled_at = NOW(), refund_amount = 0
+        WHERE status = 'Unpaid' AND payment_deadline <= NOW()"
+    ).execute(db_pool).await?;
+
+    let sales = sqlx::query_scalar!(
+        r#"WITH expired AS (
+            UPDATE sale_transaction SET status = 'Cancelled', cancelled_at = NOW(), refund_amount = 0
+            WHERE status = 'Unpaid' AND payment_deadline <= NOW()
+            RETURNING sale_transaction_id
+        ), installments AS (
+            UPDATE sale_installment SET status = 'Cancelled'
+            WHERE sale_transaction_id IN (SELECT sale_transaction_id FROM expired)
+        )
+        SELECT COUNT(*) AS "count!" FROM expired"#
+    ).fetch_one(db_pool).await?;
+
+    Ok((rents.rows_affected(), sales as u64))
+}
+
+/// Cancels expired unpaid transactions every `period`, which must not be
+/// zero, for as long as the server runs.
+pub fn spawn_reservation_expiry(db_pool: PgPool, period: StdDuration) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            match expire_unpaid_transactions(&db_pool).await {
+                Ok((0, 0)) => (),
+                Ok((rents, sales)) => info!(rents, sales, "Reservation expiry cancelled unpaid transactions"),
+                Err(err) => error!(error = %err, "Reservation expiry failed"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TransactionStatus::*;
+
+    const ALL: [TransactionStatus; 4] = [Unpaid, Installment, Paid, Cancelled];
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn policy() -> CancellationPolicy {
+        CancellationPolicy { rent_full_refund_days: 7, rent_late_refund_ratio: 0.5, sale_cancellation_fee_ratio: 0.1 }
+    }
+
+    fn allowed(kind: TransactionKind) -> Vec<(TransactionStatus, TransactionStatus)> {
+        ALL.iter()
+            .flat_map(|from| ALL.iter().map(move |to| (*from, *to)))
+            .filter(|(from, to)| from.can_become(kind, *to))
+            .collect()
+    }
+
+    #[test]
+    fn rent_transitions() {
+        assert_eq!(allowed(TransactionKind::Rent), vec![(Unpaid, Paid), (Unpaid, Cancelled), (Paid, Cancelled)]);
+    }
+
+    #[test]
+    fn sale_transitions() {
+        assert_eq!(
+            allowed(TransactionKind::Sale),
+            vec![(Unpaid, Installment), (Unpaid, Paid), (Unpaid, Cancelled), (Installment, Paid), (Installment, Cancelled)]
+        );
+    }
+
+    #[test]
+    fn installment_is_cancellable_for_sales_only() {
+        assert!(Installment.transition(TransactionKind::Sale, Cancelled).is_ok());
+        assert!(Installment.transition(TransactionKind::Rent, Cancelled).is_err());
+        assert!(Unpaid.transition(TransactionKind::Rent, Installment).is_err());
+    }
+
+    #[test]
+    fn settled_transactions_stay_settled() {
+        for kind in [TransactionKind::Rent, TransactionKind::Sale] {
+            assert_eq!(Paid.transition(kind, Unpaid), Err("Error: Transaction cannot go from Paid to Unpaid".to_string()));
+            assert!(Paid.transition(kind, Installment).is_err());
+            assert!(ALL.iter().all(|next| Cancelled.transition(kind, *next).is_err()));
+            assert!(ALL.iter().all(|status| status.transition(kind, *status).is_err()));
+        }
+
+        assert!(Paid.transition(TransactionKind::Sale, Cancelled).is_err());
+    }
+
+    #[test]
+    fn rent_refund_is_full_up_to_the_window() {
+        let start = date(2025, 6, 15);
+
+        assert_eq!(policy().rent_refund(1_000, start, date(2025, 6, 8)), Ok(1_000));
+        assert_eq!(policy().rent_refund(1_000, start, date(2025, 6, 9)), Ok(500));
+        assert_eq!(policy().rent_refund(1_000, start, date(2025, 6, 14)), Ok(500));
+    }
+
+    #[test]
+    fn started_rent_is_not_refunded() {
+        let start = date(2025, 6, 15);
+
+        assert!(policy().rent_refund(1_000, start, start).is_err());
+        assert!(policy().rent_refund(1_000, start, date(2025, 6, 16)).is_err());
+    }
+
+    #[test]
+    fn late_rent_refund_is_rounded() {
+        assert_eq!(policy().rent_refund(999, date(2025, 6, 15), date(2025, 6, 14)), Ok(500));
+    }
+
+    #[test]
+    fn sale_refund_keeps_the_fee() {
+        assert_eq!(policy().sale_refund(1_000), 900);
+        assert_eq!(policy().sale_refund(15), 13);
+        assert_eq!(policy().sale_refund(0), 0);
+        assert_eq!(CancellationPolicy { sale_cancellation_fee_ratio: 0.0, ..policy() }.sale_refund(1_000), 1_000);
+    }
+}
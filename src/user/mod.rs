@@ -1,23 +1,32 @@
-use actix_web::{get, post, web::{self, ServiceConfig}, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, patch, post, web::{self, ServiceConfig}, HttpRequest, HttpResponse};
 use bcrypt::{hash, DEFAULT_COST};
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, query_as, PgPool, Result};
 use uuid::Uuid;
 
-use crate::{utils::{get_session, models::{ApiResponse, Session}}, AppState};
+use crate::{utils::{auth::{AdminSession, AuthSession}, error::{AppError, OrNotFound}, logging::Secret, models::{ApiResponse, Session}}, AppState};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize)]
 struct UserRegistration {
     full_name : String,
     email_address: String,
     address: String,
-    password: String
+    password: Secret,
 }
 
 #[derive(Debug, Deserialize)]
 struct UserLogin {
     email_address: String,
-    password: String,
+    password: Secret,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+pub enum Role {
+    Buyer,
+    Owner,
+    Admin,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
@@ -27,41 +36,39 @@ struct User {
     email_address: String,
     address: String,
     password: String,
+    role: Role,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct UserData {
     pub user_id: Uuid,
-    full_name: String,
-    email_address: String,
-    address: String
+    pub full_name: String,
+    pub email_address: String,
+    pub address: String,
+    pub role: Role,
 }
 
 impl From<User> for UserData {
     fn from(user: User) -> Self {
-        UserData { user_id: user.user_id, full_name: user.full_name, email_address: user.email_address, address: user.address }
+        UserData { user_id: user.user_id, full_name: user.full_name, email_address: user.email_address, address: user.address, role: user.role }
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct RoleForm {
+    role: Role,
+}
+
 #[post("/api/user")]
-async fn register_user(app_state: web::Data<AppState>, user_form: web::Json<UserRegistration>) -> impl Responder {
-    println!("{:?}", user_form);
-
-    let pass_hash = match hash(user_form.password.clone(), DEFAULT_COST) {
-        Ok(hash_val) => hash_val,
-        Err(_) => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Error inserting user data".to_string(), None, Some("Unable to insert user data".to_string()))
-        )
-    };
+async fn register_user(app_state: web::Data<AppState>, user_form: web::Json<UserRegistration>) -> Result<HttpResponse, AppError> {
+    let pass_hash = hash(user_form.password.expose(), DEFAULT_COST)
+        .map_err(|err| AppError::Internal(format!("Failed hashing password: {}", err)))?;
     let user_id = Uuid::new_v4();
 
-    
-
-
     let result = query_as!(
         UserData,
         "INSERT INTO \"user\"(user_id, full_name, email_address, address, password)
-            VALUES ($1, $2, $3, $4, $5) RETURNING user_id, full_name, email_address, address",
+            VALUES ($1, $2, $3, $4, $5) RETURNING user_id, full_name, email_address, address, role AS \"role: Role\"",
         user_id,
         user_form.full_name,
         user_form.email_address,
@@ -69,131 +76,112 @@ async fn register_user(app_state: web::Data<AppState>, user_form: web::Json<User
         pass_hash
     ).fetch_one(&app_state.db_pool).await;
 
-    match result {
-        Err(err) => match err {
-            sqlx::Error::Database(db_err) => {
-                match db_err.kind() {
-                    sqlx::error::ErrorKind::UniqueViolation => return HttpResponse::BadRequest().json(
-                        ApiResponse::<()>::new(false, "User already exists".to_string(), None, Some(format!("Email: {} already registered", user_form.email_address)))
-                    ),
-                    _ => (),
-                }
-            },
-            _ => ()
-
+    let data = match result {
+        Err(sqlx::Error::Database(db_err)) if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation => {
+            return Err(AppError::AlreadyExists(format!("Email: {} already registered", user_form.email_address)));
         },
-        Ok(data) => return HttpResponse::Ok().json(
-            ApiResponse::new(true, "Successfully register new user".to_string(), Some(data), None)
-        )
-    }
+        result => result?,
+    };
 
-    HttpResponse::InternalServerError().json(
-        ApiResponse::<()>::new(false, "Unable to register user".to_string(), None, Some("Unable to register user".to_string()))
-    )
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully register new user".to_string(), Some(data), None)
+    ))
 }
 
-
-
 async fn get_user_by_email(db_pool: &PgPool, email: &str) -> Result<User> {
     let res = query_as!(
         User,
-        "SELECT * FROM \"user\" WHERE email_address = $1",
+        "SELECT user_id, full_name, email_address, address, password, role AS \"role: Role\"
+            FROM \"user\" WHERE email_address = $1",
         email
     ).fetch_one(db_pool).await?;
 
     Ok(res)
 }
 
-#[post("/api/login")]
-async fn login(app_state: web::Data<AppState>, user_login: web::Json<UserLogin>) -> impl Responder {
-    let result = get_user_by_email(&app_state.db_pool, &user_login.email_address).await;
-
+pub async fn get_user_data(db_pool: &PgPool, user_id: Uuid) -> Result<UserData> {
+    let res = query_as!(
+        UserData,
+        "SELECT user_id, full_name, email_address, address, role AS \"role: Role\"
+            FROM \"user\" WHERE user_id = $1",
+        user_id
+    ).fetch_one(db_pool).await?;
 
-    let user = match result {
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Login Failed".to_string(), None, Some("Incorrect email or password".to_string()))
-        ),
+    Ok(res)
+}
 
-        _ => return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user data".to_string(), None, Some("Internal server error occured".to_string()))
-        )
-        },
+#[post("/api/login")]
+async fn login(app_state: web::Data<AppState>, user_login: web::Json<UserLogin>) -> Result<HttpResponse, AppError> {
+    let incorrect = || AppError::BadRequest("Incorrect email or password".to_string());
 
-        Ok(user) => user
+    let user = match get_user_by_email(&app_state.db_pool, &user_login.email_address).await {
+        Err(sqlx::Error::RowNotFound) => return Err(incorrect()),
+        result => result?,
     };
 
-    let is_correct_password = match bcrypt::verify(&user_login.password, &user.password.trim()) {
-        Err(err) => {
-            println!("{:?}", err);
-            return HttpResponse::InternalServerError().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user data".to_string(), None, Some("Internal server error occured".to_string()))
-        )},
-
-        Ok(ver) => ver
-    };
+    let is_correct_password = bcrypt::verify(user_login.password.expose(), user.password.trim())
+        .map_err(|err| AppError::Internal(format!("Failed verifying password: {}", err)))?;
 
     if !is_correct_password {
-        return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Login Failed".to_string(), None, Some("Incorrect email or password".to_string()))
-        )
+        return Err(incorrect());
     }
 
     let session_id = Uuid::new_v4();
 
     let new_session = Session::new(session_id, UserData::from(user));
 
+    app_state.session_store.insert(&new_session).await.map_err(AppError::Internal)?;
 
-    let mut store_guard = app_state.session_store.lock().unwrap();
-    store_guard.insert(session_id.to_string(), new_session.clone());
-
-
-    HttpResponse::Ok().json(
+    Ok(HttpResponse::Ok().json(
         ApiResponse::new(true, "Login successful".to_string(), Some(new_session), None)
-    )
+    ))
 }
 
 #[get("/api/profile")]
-async fn get_profile(app_state: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    let user_session = match get_session(app_state, &req).await {
-        Err(err) => return HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve user session".to_string(), None, Some(err))
-        ),
-        Ok(session) => session,
-    };
-
-
+async fn get_profile(session: AuthSession) -> HttpResponse {
     HttpResponse::Ok().json(
-        ApiResponse::new(true, "Session retrieve successful".to_string(), Some(user_session), None)
+        ApiResponse::new(true, "Session retrieve successful".to_string(), Some(session.0), None)
     )
 }
 
 #[get("/api/logout")]
-async fn logout(app_state: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    let session_id = match req.headers().get("session_id") {
-        None => return  HttpResponse::BadRequest().json(
-            ApiResponse::<()>::new(false, "Unable to retrieve session data".to_string(), None, Some("Requires Header: \'session_id\'".to_string()))
-        ),
-        Some(id) => id.to_str().unwrap()
-    };
-
-    let mut session_store = app_state.session_store.lock().unwrap();
-
-    session_store.remove(session_id);
-
+async fn logout(app_state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse, AppError> {
+    let session_id = req.headers().get("session_id")
+        .ok_or_else(|| AppError::BadRequest("Requires Header: \'session_id\'".to_string()))?
+        .to_str()
+        .map_err(|_| AppError::BadRequest("Invalid Header: \'session_id\'".to_string()))?;
 
+    app_state.session_store.remove(session_id).await.map_err(AppError::Internal)?;
 
-    HttpResponse::Ok().json(
+    Ok(HttpResponse::Ok().json(
         ApiResponse::<()>::new(true, "User Logout Successful".to_string(), None, None)
-    )
+    ))
 }
 
+#[patch("/api/user/{user_id}/role")]
+async fn update_user_role(app_state: web::Data<AppState>, admin: AdminSession, user_id: web::Path<Uuid>, role_form: web::Json<RoleForm>) -> Result<HttpResponse, AppError> {
+    if admin.user_data.user_id == *user_id {
+        return Err(AppError::BadRequest("Admins cannot change their own role".to_string()));
+    }
 
+    let user = query_as!(
+        UserData,
+        "UPDATE \"user\" SET role = $2 WHERE user_id = $1
+            RETURNING user_id, full_name, email_address, address, role AS \"role: Role\"",
+        *user_id,
+        role_form.role as Role
+    ).fetch_one(&app_state.db_pool).await.or_not_found(format!("Error: No user matching id: {}", *user_id))?;
+
+    Ok(HttpResponse::Ok().json(
+        ApiResponse::new(true, "Successfully updated user role".to_string(), Some(user), None)
+    ))
+}
 
 pub fn init_routes(cfg: &mut ServiceConfig) {
     cfg
         .service(register_user)
         .service(login)
         .service(get_profile)
+        .service(update_user_role)
         .service(logout);
 }
\ No newline at end of file
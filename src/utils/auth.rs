@@ -0,0 +1,91 @@
+use std::{future::Future, ops::Deref, pin::Pin};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+
+use crate::{user::Role, AppState};
+
+use super::{error::AppError, get_session, models::Session};
+
+type SessionFuture<T> = Pin<Box<dyn Future<Output = Result<T, AppError>>>>;
+
+/// Resolves the caller's session and checks that their role is one of
+/// `allowed`. An empty `allowed` list accepts any logged-in user.
+async fn authorize(req: HttpRequest, allowed: &[Role]) -> Result<Session, AppError> {
+    let app_state = req.app_data::<web::Data<AppState>>()
+        .cloned()
+        .ok_or_else(|| AppError::Internal("AppState not configured".to_string()))?;
+
+    let session = get_session(app_state, &req).await?;
+
+    if !allowed.is_empty() && !allowed.contains(&session.user_data.role) {
+        return Err(AppError::Forbidden("User role not permitted to access this resource".to_string()));
+    }
+
+    Ok(session)
+}
+
+/// Any logged-in user.
+pub struct AuthSession(pub Session);
+
+impl Deref for AuthSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.0
+    }
+}
+
+impl FromRequest for AuthSession {
+    type Error = AppError;
+    type Future = SessionFuture<Self>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move { authorize(req, &[]).await.map(AuthSession) })
+    }
+}
+
+/// A logged-in user allowed to manage listings: owners and admins.
+pub struct OwnerSession(pub Session);
+
+impl Deref for OwnerSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.0
+    }
+}
+
+impl FromRequest for OwnerSession {
+    type Error = AppError;
+    type Future = SessionFuture<Self>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move { authorize(req, &[Role::Owner, Role::Admin]).await.map(OwnerSession) })
+    }
+}
+
+/// A logged-in admin.
+pub struct AdminSession(pub Session);
+
+impl Deref for AdminSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.0
+    }
+}
+
+impl FromRequest for AdminSession {
+    type Error = AppError;
+    type Future = SessionFuture<Self>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move { authorize(req, &[Role::Admin]).await.map(AdminSession) })
+    }
+}
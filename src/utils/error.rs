@@ -0,0 +1,199 @@
+use actix_web::{error::{JsonPayloadError, PathError, QueryPayloadError}, http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use sqlx::error::ErrorKind;
+use tracing::error;
+
+use super::models::ApiResponse;
+
+/// SQLSTATE of an exclusion constraint violation, such as two bookings of a
+/// property overlapping.
+pub const EXCLUSION_VIOLATION: &str = "23P01";
+
+/// Error returned by handlers. Each variant carries the detail shown to the
+/// client in `error`, next to a stable `code` clients can match on.
+///
+/// Database and internal errors are logged and reported without their
+/// details, which may reveal the schema or the server's internals.
+#[derive(Debug)]
+pub enum AppError {
+    /// The request is malformed or fails validation.
+    BadRequest(String),
+    /// The caller has no valid session.
+    Unauthorized(String),
+    /// The caller is not allowed to do this.
+    Forbidden(String),
+    NotFound(String),
+    /// The request clashes with the current state of the resource.
+    Conflict(String),
+    /// The resource being created already exists.
+    AlreadyExists(String),
+    /// The request body is over the configured size limit.
+    PayloadTooLarge(String),
+    /// A service the request depends on, such as the payment provider, failed.
+    Upstream(String),
+    Database(sqlx::Error),
+    Internal(String),
+}
+
+impl AppError {
+    /// Status, code, message and detail of the response.
+    fn parts(&self) -> (StatusCode, &'static str, &'static str, String) {
+        match self {
+            AppError::BadRequest(detail) => (StatusCode::BAD_REQUEST, "bad_request", "Invalid request", detail.clone()),
+            AppError::Unauthorized(detail) => (StatusCode::UNAUTHORIZED, "unauthorized", "Unauthorized", detail.clone()),
+            AppError::Forbidden(detail) => (StatusCode::FORBIDDEN, "forbidden", "Content restricted", detail.clone()),
+            AppError::NotFound(detail) => (StatusCode::NOT_FOUND, "not_found", "Not found", detail.clone()),
+            AppError::Conflict(detail) => (StatusCode::CONFLICT, "conflict", "Conflict", detail.clone()),
+            AppError::AlreadyExists(detail) => (StatusCode::CONFLICT, "already_exists", "Conflict", detail.clone()),
+            AppError::PayloadTooLarge(detail) => (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", "Payload too large", detail.clone()),
+            AppError::Upstream(detail) => (StatusCode::BAD_GATEWAY, "upstream_error", "Upstream service unavailable", detail.clone()),
+            AppError::Database(sqlx::Error::RowNotFound) => (StatusCode::NOT_FOUND, "not_found", "Not found", "Error: Resource not found".to_string()),
+            AppError::Database(sqlx::Error::Database(db_err)) => match db_err.kind() {
+                ErrorKind::UniqueViolation => (StatusCode::CONFLICT, "already_exists", "Conflict", "Error: Resource already exists".to_string()),
+                ErrorKind::ForeignKeyViolation => (StatusCode::CONFLICT, "conflict", "Conflict", "Error: Resource is referenced by or references another one".to_string()),
+                ErrorKind::CheckViolation | ErrorKind::NotNullViolation => (StatusCode::BAD_REQUEST, "bad_request", "Invalid request", "Error: Value out of range".to_string()),
+                _ if db_err.code().as_deref() == Some(EXCLUSION_VIOLATION) => (StatusCode::CONFLICT, "conflict", "Conflict", "Error: Resource overlaps an existing one".to_string()),
+                _ => (StatusCode::INTERNAL_SERVER_ERROR, "database_error", "Internal Server Error", "Error: Database error".to_string()),
+            },
+            AppError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, "database_error", "Internal Server Error", "Error: Database error".to_string()),
+            AppError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal Server Error", "Error: Internal server error".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "{}", err),
+            AppError::Internal(detail) => f.write_str(detail),
+            _ => f.write_str(&self.parts().3),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        self.parts().0
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let (status, code, message, detail) = self.parts();
+
+        if status.is_server_error() {
+            error!(code, error = %self, "Request failed");
+        }
+
+        HttpResponse::build(status).json(ApiResponse::<()>::failure(code, message.to_string(), detail))
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+/// Reports a JSON body that can't be read or deserialized in the usual
+/// envelope.
+pub fn json_error(err: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    match err {
+        JsonPayloadError::Overflow { limit } | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+            AppError::PayloadTooLarge(format!("Error: Body exceeds {} bytes", limit)).into()
+        },
+        err => AppError::BadRequest(format!("Error: {}", err)).into(),
+    }
+}
+
+/// Reports a query string that doesn't deserialize in the usual envelope.
+pub fn query_error(err: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    AppError::BadRequest(format!("Error: {}", err)).into()
+}
+
+/// Reports a path that doesn't match its parameters, such as a malformed id,
+/// as not found in the usual envelope.
+pub fn path_error(err: PathError, _: &HttpRequest) -> actix_web::Error {
+    AppError::NotFound(format!("Error: {}", err)).into()
+}
+
+pub trait OrNotFound<T> {
+    /// Reports a missing row as `AppError::NotFound` with `detail`.
+    fn or_not_found(self, detail: impl Into<String>) -> Result<T, AppError>;
+}
+
+impl<T> OrNotFound<T> for sqlx::Result<T> {
+    fn or_not_found(self, detail: impl Into<String>) -> Result<T, AppError> {
+        self.map_err(|err| match err {
+            sqlx::Error::RowNotFound => AppError::NotFound(detail.into()),
+            err => AppError::Database(err),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+    use serde::Deserialize;
+    use serde_json::Value;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Body {
+        #[allow(dead_code)]
+        name: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Filter {
+        #[allow(dead_code)]
+        min_price: Option<i64>,
+    }
+
+    async fn call(req: test::TestRequest) -> (StatusCode, Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::JsonConfig::default().limit(64).error_handler(json_error))
+                .app_data(web::QueryConfig::default().error_handler(query_error))
+                .app_data(web::PathConfig::default().error_handler(path_error))
+                .route("/json", web::post().to(|_: web::Json<Body>| async { HttpResponse::Ok().finish() }))
+                .route("/query", web::get().to(|_: web::Query<Filter>| async { HttpResponse::Ok().finish() }))
+                .route("/path/{id}", web::get().to(|_: web::Path<Uuid>| async { HttpResponse::Ok().finish() }))
+        ).await;
+
+        let res = test::call_service(&app, req.to_request()).await;
+        let status = res.status();
+
+        (status, test::read_body_json(res).await)
+    }
+
+    fn assert_envelope(body: &Value, code: &str) {
+        assert_eq!(body["success"], false);
+        assert_eq!(body["code"], code);
+        assert!(body["error"].as_str().is_some_and(|error| error.starts_with("Error: ")));
+    }
+
+    #[actix_web::test]
+    async fn json_errors_use_the_envelope() {
+        let (status, body) = call(test::TestRequest::post().uri("/json").set_json(serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_envelope(&body, "bad_request");
+
+        let (status, body) = call(test::TestRequest::post().uri("/json").set_json(serde_json::json!({ "name": "x".repeat(100) }))).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_envelope(&body, "payload_too_large");
+    }
+
+    #[actix_web::test]
+    async fn query_errors_use_the_envelope() {
+        let (status, body) = call(test::TestRequest::get().uri("/query?min_price=abc")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_envelope(&body, "bad_request");
+    }
+
+    #[actix_web::test]
+    async fn path_errors_use_the_envelope() {
+        let (status, body) = call(test::TestRequest::get().uri("/path/not-a-uuid")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_envelope(&body, "not_found");
+    }
+}
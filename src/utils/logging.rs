@@ -0,0 +1,168 @@
+use std::{convert::Infallible, fmt, str::FromStr, time::Instant};
+
+use actix_web::{body::MessageBody, dev::{ServiceRequest, ServiceResponse}, http::header::{HeaderMap, HeaderName, HeaderValue}, middleware::Next, Error};
+use serde::Deserialize;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// Header carrying the request id, taken from the caller (such as a proxy)
+/// when present and echoed on the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Headers whose values never reach the logs.
+const SENSITIVE_HEADERS: [&str; 5] = ["authorization", "cookie", "set-cookie", "session_id", "x-payment-signature"];
+
+const REDACTED: &str = "[redacted]";
+
+/// A value kept out of logs: it prints as `[redacted]`.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Infallible> {
+        Ok(Secret(value.to_string()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Installs the global subscriber, filtered by `config.log_level` and
+/// writing one JSON object per event when `config.log_format` is `json`.
+pub fn init(config: &Config) {
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&config.log_level));
+
+    match config.log_format.as_str() {
+        "json" => subscriber.json().flatten_event(true).with_current_span(true).with_span_list(false).init(),
+        _ => subscriber.init(),
+    }
+}
+
+/// `headers` as name and value pairs, with sensitive values replaced.
+pub fn redacted_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers.iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                value.to_str().unwrap_or("[non-utf8 value]").to_string()
+            };
+
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Accepts a caller's request id if it is short and plain enough to log
+/// verbatim.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Access log middleware. Runs each request in a span carrying its id, method
+/// and path, and logs its status and latency once handled.
+pub async fn request_logger(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req.headers().get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = info_span!("request", request_id = %request_id, method = %req.method(), path = %req.path());
+
+    async move {
+        let started = Instant::now();
+        debug!(headers = ?redacted_headers(req.headers()), "request received");
+
+        let result = next.call(req).await;
+        let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        match result {
+            Ok(mut res) => {
+                let status = res.status().as_u16();
+
+                if res.status().is_server_error() {
+                    error!(status, latency_ms, "request failed");
+                } else if res.status().is_client_error() {
+                    warn!(status, latency_ms, "request rejected");
+                } else {
+                    info!(status, latency_ms, "request completed");
+                }
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+
+                Ok(res)
+            },
+            Err(err) => {
+                error!(status = err.as_response_error().status_code().as_u16(), latency_ms, error = %err, "request failed");
+                Err(err)
+            },
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in pairs {
+            headers.append(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    #[test]
+    fn sensitive_headers_are_masked() {
+        let mut logged = redacted_headers(&headers(&[
+            ("cookie", "session=abc"),
+            ("authorization", "Bearer token"),
+            ("x-payment-signature", "deadbeef"),
+            ("session_id", "6f1c0f4e-5b8e-4a47-9d2a-2f4e1c3b7a10"),
+            ("content-type", "application/json"),
+            ("user-agent", "curl/8.0"),
+        ]));
+        logged.sort();
+
+        let expected: Vec<(String, String)> = [
+            ("authorization", REDACTED),
+            ("content-type", "application/json"),
+            ("cookie", REDACTED),
+            ("session_id", REDACTED),
+            ("user-agent", "curl/8.0"),
+            ("x-payment-signature", REDACTED),
+        ].iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+
+        assert_eq!(logged, expected);
+    }
+
+    #[test]
+    fn secrets_do_not_print() {
+        let secret: Secret = "hunter2".parse().unwrap();
+
+        assert_eq!(format!("{:?}", secret), REDACTED);
+        assert_eq!(secret.expose(), "hunter2");
+    }
+}
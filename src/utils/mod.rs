@@ -1,60 +1,48 @@
-use std::{fs, io, path::Path};
+use std::{fs::File, io, path::Path};
 
-use actix_multipart::form::tempfile::TempFile;
 use actix_web::{web, HttpRequest};
 use chrono::Utc;
+use sha2::{Digest, Sha256};
 use models::{ Session};
 
 use crate::AppState;
+use error::AppError;
 
+pub mod auth;
+pub mod error;
+pub mod logging;
 pub mod models;
+pub mod session_store;
 
-pub async fn save_uploaded_file(temp_file: &TempFile, target_path: &str) -> io::Result<()> {
-    let tmp_path = temp_file.file.path();
+/// Hex-encoded SHA-256 digest of the file at `path`.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
 
-    let destination = Path::new(target_path);
-
-    if let Some(parent_dir) = destination.parent() {
-        fs::create_dir_all(parent_dir)?;
-    }
-
-    fs::copy(tmp_path, destination)?;
-
-    Ok(())
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
-pub async fn get_session(app_state: web::Data<AppState>, req: &HttpRequest) -> Result<Session, String> {
-    println!("\n--- Handler Triggered: Checking Headers ---");
-    for (name, value) in req.headers().iter() {
-        println!("  > {}: {:?}", name, value.to_str().unwrap_or("[non-utf8 value]"));
-    }
-    println!("------------------------------------------\n");
-
-
+/// Resolves the session named by the `session_id` header. A missing, unknown
+/// or expired session is `Unauthorized`; failures of the session store are
+/// `Internal`, so an outage isn't mistaken for being logged out.
+pub async fn get_session(app_state: web::Data<AppState>, req: &HttpRequest) -> Result<Session, AppError> {
     let session_id = match req.headers().get("session_id") {
-        None => return Err("Required header \'session_id\'".to_string()),
-        Some(id) => id.to_str().unwrap()
+        None => return Err(AppError::Unauthorized("Required header \'session_id\'".to_string())),
+        Some(id) => id.to_str().map_err(|_| AppError::Unauthorized("Invalid header \'session_id\'".to_string()))?
     };
 
-
-
-    let mut session_store = app_state.session_store.lock().unwrap();
-    
-
-    let user_session = match session_store.get_mut(session_id) {
-        None => return Err("Session Invalid".to_string()),
+    let mut user_session = match app_state.session_store.get(session_id).await.map_err(AppError::Internal)? {
+        None => return Err(AppError::Unauthorized("Session Invalid".to_string())),
         Some(session) => session
     };
 
-    let time_from_last_online = Utc::now() - user_session.last_active;
-
-
-    if time_from_last_online.num_hours() >= 48 {
-        session_store.remove(session_id);
-        return Err("Session expired".to_string());
+    if app_state.session_policy.is_expired(&user_session) {
+        app_state.session_store.remove(session_id).await.map_err(AppError::Internal)?;
+        return Err(AppError::Unauthorized("Session expired".to_string()));
     }
 
+    app_state.session_store.touch(session_id).await.map_err(AppError::Internal)?;
     user_session.last_active = Utc::now();
 
-    Ok(user_session.clone())
-}
\ No newline at end of file
+    Ok(user_session)
+}
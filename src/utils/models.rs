@@ -8,27 +8,35 @@ use crate::user::UserData;
 pub struct ApiResponse<T> {
     success: bool,
     message: String,
+    /// Machine-readable error code, only present on failures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
     data: Option<T>,
     error: Option<String>
 }
 
 impl<T> ApiResponse<T> {
     pub fn new(success: bool, message: String, data: Option<T>, error: Option<String>) -> Self {
-        ApiResponse { success, message, data, error}
+        ApiResponse { success, message, code: None, data, error}
+    }
+
+    pub fn failure(code: &'static str, message: String, error: String) -> Self {
+        ApiResponse { success: false, message, code: Some(code), data: None, error: Some(error) }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Session {
-    session_id: Uuid,
+    pub session_id: Uuid,
     pub user_data: UserData,
+    pub created_at: DateTime<Utc>,
     pub last_active: DateTime<Utc>,
 }
 
 impl Session {
     pub fn new(session_id: Uuid, user_data: UserData) -> Self {
-        let last_active = Utc::now();
+        let created_at = Utc::now();
 
-        Session { session_id, user_data, last_active }
+        Session { session_id, user_data, created_at, last_active: created_at }
     }
 }
\ No newline at end of file
@@ -0,0 +1,355 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration as StdDuration};
+
+use actix_web::rt;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{config::Config, user::get_user_data};
+
+use super::models::Session;
+
+/// How long a session may stay idle and how long it may live in total,
+/// regardless of activity.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionPolicy {
+    pub idle_timeout: Duration,
+    pub max_lifetime: Duration,
+}
+
+impl SessionPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        SessionPolicy {
+            idle_timeout: Duration::hours(config.session_idle_timeout_hours),
+            max_lifetime: Duration::hours(config.session_max_lifetime_hours),
+        }
+    }
+
+    /// The moment a session becomes invalid if it sees no further activity.
+    pub fn expires_at(&self, created_at: DateTime<Utc>, last_active: DateTime<Utc>) -> DateTime<Utc> {
+        (last_active + self.idle_timeout).min(created_at + self.max_lifetime)
+    }
+
+    pub fn is_expired(&self, session: &Session) -> bool {
+        Utc::now() >= self.expires_at(session.created_at, session.last_active)
+    }
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert(&self, session: &Session) -> Result<(), String>;
+
+    async fn get(&self, session_id: &str) -> Result<Option<Session>, String>;
+
+    /// Marks the session as active now, pushing back its expiry.
+    async fn touch(&self, session_id: &str) -> Result<(), String>;
+
+    async fn remove(&self, session_id: &str) -> Result<(), String>;
+
+    /// Evicts every expired session, returning how many were removed.
+    async fn remove_expired(&self) -> Result<u64, String>;
+
+    /// Number of sessions that have not expired.
+    async fn count(&self) -> Result<u64, String>;
+}
+
+/// Keeps sessions in process memory. Everything is lost on restart, so this is
+/// only meant for tests and local development.
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    policy: SessionPolicy,
+}
+
+impl MemorySessionStore {
+    pub fn new(policy: SessionPolicy) -> Self {
+        MemorySessionStore { sessions: Mutex::new(HashMap::new()), policy }
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn insert(&self, session: &Session) -> Result<(), String> {
+        let mut sessions = self.sessions.lock().map_err(|err| err.to_string())?;
+        sessions.insert(session.session_id.to_string(), session.clone());
+
+        Ok(())
+    }
+
+    async fn get(&self, session_id: &str) -> Result<Option<Session>, String> {
+        let sessions = self.sessions.lock().map_err(|err| err.to_string())?;
+
+        Ok(sessions.get(session_id).cloned())
+    }
+
+    async fn touch(&self, session_id: &str) -> Result<(), String> {
+        let mut sessions = self.sessions.lock().map_err(|err| err.to_string())?;
+
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.last_active = Utc::now();
+        }
+
+        Ok(())
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<(), String> {
+        let mut sessions = self.sessions.lock().map_err(|err| err.to_string())?;
+        sessions.remove(session_id);
+
+        Ok(())
+    }
+
+    async fn remove_expired(&self) -> Result<u64, String> {
+        let mut sessions = self.sessions.lock().map_err(|err| err.to_string())?;
+        let before = sessions.len();
+
+        sessions.retain(|_, session| !self.policy.is_expired(session));
+
+        Ok((before - sessions.len()) as u64)
+    }
+
+    async fn count(&self) -> Result<u64, String> {
+        let sessions = self.sessions.lock().map_err(|err| err.to_string())?;
+
+        Ok(sessions.values().filter(|session| !self.policy.is_expired(session)).count() as u64)
+    }
+}
+
+/// Stores sessions in the `user_session` table so they survive restarts and
+/// can be shared by every server instance using the same database.
+pub struct PgSessionStore {
+    db_pool: PgPool,
+    policy: SessionPolicy,
+}
+
+impl PgSessionStore {
+    pub fn new(db_pool: PgPool, policy: SessionPolicy) -> Self {
+        PgSessionStore { db_pool, policy }
+    }
+}
+
+#[async_trait]
+impl SessionStore for PgSessionStore {
+    async fn insert(&self, session: &Session) -> Result<(), String> {
+        let expires_at = self.policy.expires_at(session.created_at, session.last_active);
+
+        sqlx::query!(
+            "INSERT INTO user_session(session_id, user_id, created_at, last_active, expires_at)
+                VALUES($1, $2, $3, $4, $5)",
+            session.session_id,
+            session.user_data.user_id,
+            session.created_at,
+            session.last_active,
+            expires_at
+        ).execute(&self.db_pool).await.map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn get(&self, session_id: &str) -> Result<Option<Session>, String> {
+        let session_id = match Uuid::parse_str(session_id) {
+            Ok(id) => id,
+            Err(_) => return Ok(None)
+        };
+
+        let row = sqlx::query!(
+            "SELECT user_id, created_at, last_active FROM user_session WHERE session_id = $1",
+            session_id
+        ).fetch_optional(&self.db_pool).await.map_err(|err| err.to_string())?;
+
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row
+        };
+
+        let user_data = match get_user_data(&self.db_pool, row.user_id).await {
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(err) => return Err(err.to_string()),
+            Ok(data) => data
+        };
+
+        Ok(Some(Session { session_id, user_data, created_at: row.created_at, last_active: row.last_active }))
+    }
+
+    async fn touch(&self, session_id: &str) -> Result<(), String> {
+        let session_id = match Uuid::parse_str(session_id) {
+            Ok(id) => id,
+            Err(_) => return Ok(())
+        };
+
+        let now = Utc::now();
+
+        sqlx::query!(
+            "UPDATE user_session SET last_active = $2, expires_at = LEAST($3, created_at + make_interval(secs => $4))
+                WHERE session_id = $1",
+            session_id,
+            now,
+            now + self.policy.idle_timeout,
+            self.policy.max_lifetime.num_seconds() as f64
+        ).execute(&self.db_pool).await.map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<(), String> {
+        let session_id = match Uuid::parse_str(session_id) {
+            Ok(id) => id,
+            Err(_) => return Ok(())
+        };
+
+        sqlx::query!(
+            "DELETE FROM user_session WHERE session_id = $1",
+            session_id
+        ).execute(&self.db_pool).await.map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    async fn remove_expired(&self) -> Result<u64, String> {
+        let result = sqlx::query!(
+            "DELETE FROM user_session WHERE expires_at <= NOW()"
+        ).execute(&self.db_pool).await.map_err(|err| err.to_string())?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn count(&self) -> Result<u64, String> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) AS \"count!\" FROM user_session WHERE expires_at > NOW()"
+        ).fetch_one(&self.db_pool).await.map_err(|err| err.to_string())?;
+
+        Ok(count as u64)
+    }
+}
+
+/// Evicts expired sessions from `store` every `period`, which must not be
+/// zero, for as long as the server runs.
+pub fn spawn_session_sweeper(store: Arc<dyn SessionStore>, period: StdDuration) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            match store.remove_expired().await {
+                Ok(0) => (),
+                Ok(count) => info!(count, "Session sweeper evicted expired sessions"),
+                Err(err) => error!(error = %err, "Session sweeper failed"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::user::{Role, UserData};
+
+    use super::*;
+
+    fn policy() -> SessionPolicy {
+        SessionPolicy { idle_timeout: Duration::hours(2), max_lifetime: Duration::hours(24) }
+    }
+
+    fn session(created_ago: Duration, active_ago: Duration) -> Session {
+        let now = Utc::now();
+
+        Session {
+            session_id: Uuid::new_v4(),
+            user_data: UserData {
+                user_id: Uuid::new_v4(),
+                full_name: "Test User".to_string(),
+                email_address: "test@example.com".to_string(),
+                address: "Street".to_string(),
+                role: Role::Buyer,
+            },
+            created_at: now - created_ago,
+            last_active: now - active_ago,
+        }
+    }
+
+    #[test]
+    fn idle_timeout_applies_while_within_lifetime() {
+        let created_at = Utc::now();
+        let last_active = created_at + Duration::hours(5);
+
+        assert_eq!(policy().expires_at(created_at, last_active), last_active + Duration::hours(2));
+    }
+
+    #[test]
+    fn max_lifetime_caps_an_active_session() {
+        let created_at = Utc::now();
+        let last_active = created_at + Duration::hours(23);
+
+        assert_eq!(policy().expires_at(created_at, last_active), created_at + Duration::hours(24));
+    }
+
+    #[test]
+    fn idle_session_expires() {
+        assert!(!policy().is_expired(&session(Duration::hours(3), Duration::minutes(119))));
+        assert!(policy().is_expired(&session(Duration::hours(3), Duration::minutes(121))));
+    }
+
+    #[test]
+    fn old_session_expires_despite_activity() {
+        assert!(!policy().is_expired(&session(Duration::hours(23), Duration::zero())));
+        assert!(policy().is_expired(&session(Duration::hours(24) + Duration::seconds(1), Duration::zero())));
+    }
+
+    #[test]
+    fn session_is_expired_at_its_expiry() {
+        // Idle for exactly the timeout: the expiry moment is already past by
+        // the time it is checked.
+        assert!(policy().is_expired(&session(Duration::hours(3), Duration::hours(2))));
+        assert!(policy().is_expired(&session(Duration::hours(24), Duration::zero())));
+    }
+
+    #[actix_web::test]
+    async fn memory_store_round_trips_sessions() {
+        let store = MemorySessionStore::new(policy());
+        let stored = session(Duration::hours(1), Duration::hours(1));
+        let id = stored.session_id.to_string();
+
+        store.insert(&stored).await.unwrap();
+        assert_eq!(store.get(&id).await.unwrap().map(|session| session.session_id), Some(stored.session_id));
+
+        store.touch(&id).await.unwrap();
+        assert!(store.get(&id).await.unwrap().unwrap().last_active > stored.last_active);
+
+        store.remove(&id).await.unwrap();
+        assert!(store.get(&id).await.unwrap().is_none());
+        assert!(store.get("unknown").await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn memory_store_evicts_only_expired_sessions() {
+        let store = MemorySessionStore::new(policy());
+        let active = session(Duration::hours(1), Duration::minutes(5));
+        let idle = session(Duration::hours(5), Duration::hours(3));
+        let too_old = session(Duration::hours(25), Duration::minutes(5));
+
+        for session in [&active, &idle, &too_old] {
+            store.insert(session).await.unwrap();
+        }
+
+        assert_eq!(store.count().await.unwrap(), 1);
+        assert_eq!(store.remove_expired().await.unwrap(), 2);
+        assert_eq!(store.remove_expired().await.unwrap(), 0);
+
+        assert!(store.get(&active.session_id.to_string()).await.unwrap().is_some());
+        assert!(store.get(&idle.session_id.to_string()).await.unwrap().is_none());
+        assert!(store.get(&too_old.session_id.to_string()).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn touch_keeps_an_idle_session_alive() {
+        let store = MemorySessionStore::new(policy());
+        let nearly_idle = session(Duration::hours(3), Duration::minutes(119));
+
+        store.insert(&nearly_idle).await.unwrap();
+        store.touch(&nearly_idle.session_id.to_string()).await.unwrap();
+
+        assert_eq!(store.remove_expired().await.unwrap(), 0);
+        assert_eq!(store.count().await.unwrap(), 1);
+    }
+}